@@ -0,0 +1,63 @@
+//! Compares mapping offsets to positions with a [`LineIndex`] to scanning the
+//! document for every offset.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use lsp_types::{Position, PositionEncodingKind};
+
+// the crate has no library to link against
+#[allow(dead_code)]
+#[path = "../src/line_index.rs"]
+mod line_index;
+
+use line_index::LineIndex;
+
+/// Position of `offset` found by scanning `document` up to it.
+fn scan(document: &str, offset: usize) -> Position {
+    let line_start = document[..offset]
+        .rfind('\n')
+        .map_or(0, |newline| newline + 1);
+    Position {
+        line: u32::try_from(document[..offset].matches('\n').count()).unwrap(),
+        character: u32::try_from(document[line_start..offset].encode_utf16().count()).unwrap(),
+    }
+}
+
+fn positions(c: &mut Criterion) {
+    let document = "/// Some täxt with a wrld in it.\nfn f() {}\n".repeat(1_000);
+    let offsets: Vec<_> = document
+        .match_indices("wrld")
+        .map(|(offset, _)| offset)
+        .collect();
+    let encoding = PositionEncodingKind::UTF16;
+    let index = LineIndex::new(&document, &encoding);
+    assert!(
+        offsets
+            .iter()
+            .all(|&offset| index.position(offset) == scan(&document, offset))
+    );
+
+    let mut group = c.benchmark_group("positions");
+    group.bench_function("scanning", |b| {
+        b.iter(|| {
+            offsets
+                .iter()
+                .map(|&offset| scan(black_box(&document), offset))
+                .collect::<Vec<_>>()
+        });
+    });
+    group.bench_function("line index", |b| {
+        b.iter(|| {
+            let index = LineIndex::new(black_box(&document), &encoding);
+            offsets
+                .iter()
+                .map(|&offset| index.position(offset))
+                .collect::<Vec<_>>()
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, positions);
+criterion_main!(benches);
@@ -0,0 +1,24 @@
+use std::fs::File;
+use std::path::Path;
+
+use zip::ZipArchive;
+
+const ZIP: &str = "LanguageTool-stable.zip";
+
+fn main() {
+    println!("cargo:rerun-if-changed={ZIP}");
+    let zip = ZipArchive::new(File::open(ZIP).expect("embedded zip should exist"))
+        .expect("embedded zip file should be valid");
+    let root_dir = zip
+        .file_names()
+        .next()
+        .map(Path::new)
+        .expect("embedded server should contain files")
+        .components()
+        .next()
+        .expect("files in server should have a root component")
+        .as_os_str()
+        .to_str()
+        .expect("paths in embedded server should be valid utf8");
+    println!("cargo:rustc-env=LANGUAGE_TOOL_ROOT_DIR={root_dir}");
+}
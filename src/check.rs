@@ -0,0 +1,403 @@
+//! Headless mode checking files without an editor, e.g., in CI.
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::{env, fs};
+
+use anyhow::{bail, Context as _};
+use lsp_types::{
+    Diagnostic, DiagnosticSeverity, NumberOrString, Position, PositionEncodingKind, Range, Url,
+};
+use serde_json::{json, Value};
+
+use crate::diagnostic::{cache_location, diagnose, Checker, Meta};
+use crate::state::State;
+use crate::{config, start_server, state};
+
+const USAGE: &str = "usage: doc-spelling-lsp check [--config <file>] [--language <id>] [--format \
+                     human|json|github] <files>...";
+
+/// Exit code for errors, e.g., invalid arguments or an unreachable server, to
+/// tell them apart from found issues, which exit with [`ExitCode::FAILURE`].
+const ERROR_EXIT_CODE: u8 = 2;
+
+/// How diagnostics are printed.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+enum Format {
+    /// `file:line:column: message`
+    #[default]
+    Human,
+    /// One array of all diagnostics, printed after all files were checked.
+    Json,
+    /// GitHub Actions workflow commands, shown as annotations.
+    Github,
+}
+
+/// Command line arguments of [`run`].
+#[derive(Default, Debug, PartialEq, Eq)]
+struct Arguments {
+    config_file: Option<String>,
+    language_id: Option<String>,
+    format: Format,
+    files: Vec<PathBuf>,
+}
+
+impl Arguments {
+    /// Parses `args`, `None` if only the usage was requested.
+    fn parse(mut args: impl Iterator<Item = String>) -> anyhow::Result<Option<Self>> {
+        let mut arguments = Self::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => arguments.config_file = Some(args.next().context(USAGE)?),
+                "--language" => arguments.language_id = Some(args.next().context(USAGE)?),
+                "--format" => {
+                    arguments.format = match args.next().context(USAGE)?.as_str() {
+                        "human" => Format::Human,
+                        "json" => Format::Json,
+                        "github" => Format::Github,
+                        other => bail!("unknown format `{other}`\n{USAGE}"),
+                    }
+                }
+                "--help" | "-h" => return Ok(None),
+                _ if arg.starts_with("--") => bail!("unknown option `{arg}`\n{USAGE}"),
+                _ => arguments.files.push(PathBuf::from(arg)),
+            }
+        }
+        if arguments.files.is_empty() {
+            bail!(USAGE);
+        }
+        Ok(Some(arguments))
+    }
+}
+
+/// Checks the files in `args` and prints the diagnostics in the `--format`,
+/// by default as `file:line:column: message`.
+///
+/// Returns [`ExitCode::FAILURE`] if any diagnostics were found and
+/// [`ERROR_EXIT_CODE`] if checking failed.
+pub async fn run(args: impl Iterator<Item = String>) -> ExitCode {
+    match check(args).await {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(ERROR_EXIT_CODE)
+        }
+    }
+}
+
+async fn check(args: impl Iterator<Item = String>) -> anyhow::Result<ExitCode> {
+    let Some(Arguments {
+        config_file,
+        language_id,
+        format,
+        files,
+    }) = Arguments::parse(args)?
+    else {
+        println!("{USAGE}");
+        return Ok(ExitCode::SUCCESS);
+    };
+
+    // like an editor opened in the current directory
+    let workspace = env::current_dir().ok();
+    // same format as the `initializationOptions`
+    let settings = if let Some(config_file) = config_file {
+        let config = fs::read(&config_file)
+            .with_context(|| format!("unable to read config `{config_file}`"))?;
+        Some(
+            serde_json::from_slice(&config)
+                .with_context(|| format!("unable to deserialize config `{config_file}`"))?,
+        )
+    } else {
+        None
+    };
+    let project_config = workspace.as_deref().map(config::project_file);
+    let config = config::load(settings, project_config.as_deref()).map_err(anyhow::Error::msg)?;
+    let mut state = if let Some(location) = state::location(&config.state, workspace.as_deref())? {
+        state::read(&location)?
+    } else {
+        State::default()
+    };
+    state.external_dictionary = state::load_dictionaries(&config.state.dictionary_files);
+    if let Some(workspace) = &workspace {
+        state.workspace_dictionary =
+            state::load_workspace_dictionary(&state::workspace_dictionary(workspace));
+    }
+
+    let (mut server, checker) = start_server(&config, None).await?;
+    let cache_location = cache_location().filter(|_| config.diagnostics.persistent_cache);
+    if let Some(cache_location) = &cache_location {
+        checker.load_cache(cache_location);
+    }
+    let result = check_files(
+        &files,
+        language_id.as_deref(),
+        format,
+        &checker,
+        &state,
+        &config,
+    )
+    .await;
+    if let Some(server) = &mut server {
+        _ = server.kill();
+    }
+    checker.log_cache_metrics();
+    if let Some(cache_location) = &cache_location {
+        if let Err(e) = checker.save_cache(cache_location) {
+            eprintln!("{e:#}");
+        }
+    }
+    result
+}
+
+async fn check_files(
+    files: &[PathBuf],
+    language_id: Option<&str>,
+    format: Format,
+    checker: &Checker,
+    state: &State,
+    config: &config::Config,
+) -> anyhow::Result<ExitCode> {
+    let mut found = 0;
+    let mut json = Vec::new();
+    for file in files {
+        let text = fs::read_to_string(file)
+            .with_context(|| format!("unable to read `{}`", file.display()))?;
+        let Some(language_id) = language_id.or_else(|| {
+            file.extension()
+                .and_then(OsStr::to_str)
+                .and_then(|extension| config.diagnostics.extension_language_id(extension))
+        }) else {
+            bail!(
+                "unable to infer language of `{}`, use `--language`",
+                file.display()
+            );
+        };
+        // settings for documents in the editor
+        let state = match fs::canonicalize(file)
+            .ok()
+            .and_then(|path| Url::from_file_path(path).ok())
+        {
+            Some(uri) => state.for_document(config, &uri, language_id),
+            None => state.clone(),
+        };
+        let diagnostics = diagnose(
+            &text,
+            language_id,
+            // columns in characters
+            &PositionEncodingKind::UTF32,
+            checker,
+            &state,
+            &config.diagnostics,
+        )
+        .await?;
+        for diagnostic in &diagnostics {
+            match format {
+                Format::Human => print_human(file, diagnostic),
+                Format::Json => json.push(to_json(file, diagnostic)),
+                Format::Github => print_github(file, diagnostic),
+            }
+        }
+        found += diagnostics.len();
+    }
+    if let Format::Json = format {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json).expect("diagnostics can be serialized")
+        );
+    }
+    if found == 0 {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        eprintln!("found {found} issues");
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+fn print_human(file: &Path, diagnostic: &Diagnostic) {
+    let Position { line, character } = diagnostic.range.start;
+    println!(
+        "{}:{}:{}: {}",
+        file.display(),
+        line + 1,
+        character + 1,
+        diagnostic.message
+    );
+}
+
+/// Code of the rule, if any.
+fn code(diagnostic: &Diagnostic) -> Option<String> {
+    diagnostic.code.as_ref().map(|code| match code {
+        NumberOrString::Number(code) => code.to_string(),
+        NumberOrString::String(code) => code.clone(),
+    })
+}
+
+/// Lines and columns are one-based, like in [`Format::Human`].
+fn to_json(file: &Path, diagnostic: &Diagnostic) -> Value {
+    let Range { start, end } = diagnostic.range;
+    let replacements = diagnostic
+        .data
+        .clone()
+        .and_then(|data| serde_json::from_value::<Meta>(data).ok())
+        .map(|meta| meta.replacements)
+        .unwrap_or_default();
+    json!({
+        "file": file,
+        "line": start.line + 1,
+        "column": start.character + 1,
+        "end_line": end.line + 1,
+        "end_column": end.character + 1,
+        "severity": severity(diagnostic),
+        "code": code(diagnostic),
+        "message": diagnostic.message,
+        "replacements": replacements,
+    })
+}
+
+fn severity(diagnostic: &Diagnostic) -> &'static str {
+    match diagnostic.severity {
+        Some(DiagnosticSeverity::ERROR) => "error",
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        Some(DiagnosticSeverity::HINT) => "hint",
+        _ => "information",
+    }
+}
+
+fn print_github(file: &Path, diagnostic: &Diagnostic) {
+    println!("{}", github_command(file, diagnostic));
+}
+
+/// Workflow command annotating `diagnostic`, see
+/// <https://docs.github.com/en/actions/using-workflows/workflow-commands-for-github-actions>.
+fn github_command(file: &Path, diagnostic: &Diagnostic) -> String {
+    // annotations only support these levels
+    let level = match diagnostic.severity {
+        Some(DiagnosticSeverity::ERROR) => "error",
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        _ => "notice",
+    };
+    let Range { start, end } = diagnostic.range;
+    let mut properties = format!(
+        "file={},line={},col={}",
+        escape_property(&file.display().to_string()),
+        start.line + 1,
+        start.character + 1
+    );
+    // multi line annotations don't support columns
+    if start.line == end.line {
+        properties += &format!(",endColumn={}", end.character + 1);
+    } else {
+        properties += &format!(",endLine={}", end.line + 1);
+    }
+    if let Some(code) = code(diagnostic) {
+        properties += &format!(",title={}", escape_property(&code));
+    }
+    format!(
+        "::{level} {properties}::{}",
+        escape_data(&diagnostic.message)
+    )
+}
+
+fn escape_data(data: &str) -> String {
+    data.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(property: &str) -> String {
+    escape_data(property)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    fn diagnostic(start: (u32, u32), end: (u32, u32)) -> Diagnostic {
+        Diagnostic {
+            range: Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1)),
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: Some(NumberOrString::String("MORFOLOGIK_RULE_EN_US".to_owned())),
+            message: "Possible spelling mistake: 100%\nfound.".to_owned(),
+            data: Some(json!({ "replacements": ["world"] })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let arguments = Arguments::parse(args(&["--format", "json", "a.md", "b.rs"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(arguments, Arguments {
+            format: Format::Json,
+            files: vec!["a.md".into(), "b.rs".into()],
+            ..Default::default()
+        });
+        assert_eq!(Arguments::parse(args(&["--help"])).unwrap(), None);
+        let error = Arguments::parse(args(&["--format", "xml", "a.md"])).unwrap_err();
+        assert_eq!(error.to_string(), format!("unknown format `xml`\n{USAGE}"));
+        let error = Arguments::parse(args(&["--language", "markdown"])).unwrap_err();
+        assert_eq!(error.to_string(), USAGE);
+    }
+
+    #[tokio::test]
+    async fn errors_exit_with_distinct_code() {
+        assert_eq!(
+            run(args(&["--format", "xml", "a.md"])).await,
+            ExitCode::from(ERROR_EXIT_CODE)
+        );
+        assert_ne!(ExitCode::from(ERROR_EXIT_CODE), ExitCode::FAILURE);
+    }
+
+    #[test]
+    fn escapes_workflow_commands() {
+        assert_eq!(escape_data("100%\r\nb: c, d"), "100%25%0D%0Ab: c, d");
+        assert_eq!(
+            escape_property("100%\r\nb: c, d"),
+            "100%25%0D%0Ab%3A c%2C d"
+        );
+    }
+
+    #[test]
+    fn github_command_uses_end_column_only_on_one_line() {
+        let file = Path::new("src/a,b.rs");
+        assert_eq!(
+            github_command(file, &diagnostic((1, 4), (1, 8))),
+            "::warning file=src/a%2Cb.rs,line=2,col=5,endColumn=9,title=MORFOLOGIK_RULE_EN_US::\
+             Possible spelling mistake: 100%25%0Afound."
+        );
+        assert_eq!(
+            github_command(file, &diagnostic((1, 4), (2, 0))),
+            "::warning file=src/a%2Cb.rs,line=2,col=5,endLine=3,title=MORFOLOGIK_RULE_EN_US::\
+             Possible spelling mistake: 100%25%0Afound."
+        );
+    }
+
+    #[test]
+    fn json_positions_are_one_based() {
+        assert_eq!(
+            to_json(Path::new("a.md"), &diagnostic((0, 0), (2, 3))),
+            json!({
+                "file": "a.md",
+                "line": 1,
+                "column": 1,
+                "end_line": 3,
+                "end_column": 4,
+                "severity": "warning",
+                "code": "MORFOLOGIK_RULE_EN_US",
+                "message": "Possible spelling mistake: 100%\nfound.",
+                "replacements": ["world"],
+            })
+        );
+    }
+}
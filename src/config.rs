@@ -1,15 +1,182 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 
+use log::warn;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use smart_default::SmartDefault;
 
-#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+/// JSON Schema of [`Config`], for editors to validate and complete settings.
+pub fn schema() -> String {
+    serde_json::to_string_pretty(&schemars::schema_for!(Config))
+        .expect("schema should be serializable")
+}
+
+/// `initializationOptions` and settings of doc-spelling-lsp, also used for the
+/// [`project_file`], which can only set the [`PROJECT_SECTIONS`].
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone)]
 pub struct Config {
+    #[serde(default)]
     pub server: Server,
+    #[serde(default)]
     pub state: State,
+    #[serde(default)]
+    pub diagnostics: Diagnostics,
+    /// Languages by language id or `*.<extension>`, e.g., `{ "markdown":
+    /// "en-US", "*.tex": "de-DE" }`, used for documents without a language
+    /// set by `SetLanguage` for the document. Other globs are not supported.
+    #[serde(default)]
+    pub languages: HashMap<String, String>,
+}
+
+impl Config {
+    /// Checks values that cannot be expressed in the types.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Server::Online {
+            requests_per_minute: Some(0),
+            ..
+        } = self.server
+        {
+            return Err("`server.requests_per_minute` must be at least 1".to_owned());
+        }
+        // `*.<extension>` is matched literally, other globs would never match
+        let is_glob = |key: &str| key.contains(['*', '?', '[', ']', '{', '}', '/', '\\']);
+        let is_extension = |key: &str| {
+            key.strip_prefix("*.")
+                .is_some_and(|extension| !extension.is_empty() && !is_glob(extension))
+        };
+        if let Some(key) = self
+            .languages
+            .keys()
+            .find(|key| is_glob(key) && !is_extension(key))
+        {
+            return Err(format!(
+                "`languages` only supports language ids and `*.<extension>`, not `{key}`"
+            ));
+        }
+        self.diagnostics.validate()
+    }
+
+    /// Language configured for `document` by extension, or else by
+    /// `language_id`.
+    pub fn language(&self, document: &lsp_types::Url, language_id: &str) -> Option<&str> {
+        let extension = document
+            .path_segments()
+            .and_then(Iterator::last)
+            .and_then(|name| name.rsplit_once('.'))
+            .map(|(_, extension)| format!("*.{extension}"));
+        extension
+            .and_then(|extension| self.languages.get(&extension))
+            .or_else(|| self.languages.get(language_id))
+            .map(String::as_str)
+    }
+}
+
+/// Configuration of a workspace, meant to be committed with the project, in
+/// the same format as the `initializationOptions`, either as `config.toml` or
+/// `config.json`.
+///
+/// It is read from the `.doc-spelling` directory next to the workspace state
+/// and dictionary, not from a `.doc-spelling.toml` in the workspace root, and
+/// only the [`PROJECT_SECTIONS`] are applied.
+pub fn project_file(workspace: &Path) -> PathBuf {
+    let dir = workspace.join(".doc-spelling");
+    let toml = dir.join("config.toml");
+    if toml.exists() {
+        toml
+    } else {
+        dir.join("config.json")
+    }
+}
+
+/// Sections the [`project_file`] can set, others, e.g., `server`, could run
+/// any program chosen by a repository.
+///
+/// Of `state` only the `dictionary_files` are applied, relative ones are
+/// resolved against the directory of the project file.
+pub const PROJECT_SECTIONS: [&str; 3] = ["diagnostics", "languages", "state"];
+
+/// Deserializes `settings`, e.g., the `initializationOptions`, layered over
+/// the configuration in `project_file`, if it exists.
+///
+/// Objects are merged by key, so the settings only need to contain the values
+/// that differ from the project.
+pub fn load(settings: Option<Value>, project_file: Option<&Path>) -> Result<Config, String> {
+    let mut config = match project_file.filter(|file| file.exists()) {
+        Some(file) => read_project_file(file)
+            .map_err(|e| format!("unable to read project config `{}`: {e}", file.display()))?,
+        None => Value::Object(serde_json::Map::new()),
+    };
+    if let Some(settings) = settings.filter(|settings| !settings.is_null()) {
+        merge(&mut config, settings);
+    }
+    let config: Config = serde_json::from_value(config).map_err(|e| e.to_string())?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// Reads the [`project_file`] `file`, ignoring everything but the
+/// [`PROJECT_SECTIONS`] with a warning.
+fn read_project_file(file: &Path) -> Result<Value, String> {
+    let content = fs::read_to_string(file).map_err(|e| e.to_string())?;
+    let is_toml = file.extension().is_some_and(|extension| extension == "toml");
+    let project: Value = if is_toml {
+        toml::from_str(&content).map_err(|e| e.to_string())?
+    } else {
+        serde_json::from_str(&content).map_err(|e| e.to_string())?
+    };
+    let Value::Object(mut sections) = project else {
+        return Err("expected an object of settings".to_owned());
+    };
+    let dir = file.parent().unwrap_or(Path::new(""));
+    let ignore = |key: &str| {
+        warn!(
+            "ignoring `{key}` in project config `{}`, it can only be set in the editor settings",
+            file.display()
+        );
+    };
+    sections.retain(|section, value| {
+        if !PROJECT_SECTIONS.contains(&section.as_str()) {
+            ignore(section);
+            return false;
+        }
+        if let ("state", Value::Object(state)) = (section.as_str(), value) {
+            state.retain(|key, _| {
+                let dictionary = key == "dictionary_files";
+                if !dictionary {
+                    ignore(&format!("state.{key}"));
+                }
+                dictionary
+            });
+            // the editor would resolve them against its working directory
+            if let Some(Value::Array(files)) = state.get_mut("dictionary_files") {
+                for file in files {
+                    if let Value::String(path) = file {
+                        *path = dir.join(&*path).to_string_lossy().into_owned();
+                    }
+                }
+            }
+        }
+        true
+    });
+    Ok(Value::Object(sections))
 }
 
-#[derive(Serialize, Deserialize, SmartDefault, Debug, Clone)]
+/// Merges `overrides` into `base`, objects by key, other values are replaced.
+fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, SmartDefault, Debug, Clone, PartialEq, Eq)]
 #[serde(tag = "type")]
 pub enum Server {
     #[default]
@@ -23,14 +190,48 @@ pub enum Server {
         /// | Linux    | `$XDG_DATA_HOME/doc-spelling-lsp` or `$HOME/.local/share/doc-spelling-lsp` |
         /// | macOS    | `$HOME/Library/Application Support/doc-spelling-lsp`                       |
         /// | Windows  | `{FOLDERID_RoamingAppData}\doc-spelling-lsp`                               |
+        ///
+        /// Falls back to the temporary directory if no home directory can be
+        /// found.
         location: Option<PathBuf>,
+        /// Java executable to run the server with, needs at least Java 17.
+        ///
+        /// Default is `$JAVA_HOME/bin/java` if `JAVA_HOME` is set, otherwise
+        /// `java`.
+        java_executable: Option<PathBuf>,
+        /// Arguments passed to the JVM, i.e., before the main class, e.g.,
+        /// `-Xmx512m`.
+        #[serde(default)]
+        jvm_args: Vec<String>,
         #[serde(flatten)]
         config: LocalServer,
     },
+    /// Remote or already running LanguageTool server, e.g.,
+    /// `https://api.languagetoolplus.com` or a container shared by editors.
+    ///
+    /// `External` is accepted as alias.
+    #[serde(alias = "External")]
     Online {
-        // TODO
+        /// Url of the server, excluding the `/v2` api path.
+        ///
+        /// Default is the public `https://api.languagetool.org`, `url` is
+        /// accepted as alias.
+        #[serde(alias = "url")]
+        base_url: Option<String>,
+        /// Port of the server, if it is not part of `base_url`.
+        port: Option<u16>,
+        /// Credentials for premium accounts.
+        credentials: Option<Credentials>,
+        /// Maximum number of checks sent per minute.
+        ///
+        /// Default is the limit of the public api, i.e., 20, unless `base_url`
+        /// or `credentials` are configured.
+        requests_per_minute: Option<u32>,
     },
+    /// Installed LanguageTool server.
     Local {
+        /// Executable starting the server, e.g., a wrapper script, or the
+        /// `languagetool-server.jar` to run with `java`.
         #[serde(default = "default_executable")]
         executable: String,
         #[serde(flatten)]
@@ -38,21 +239,48 @@ pub enum Server {
     },
 }
 
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: String,
+    pub api_key: String,
+}
+
 fn default_executable() -> String {
     "languagetool".into()
 }
 
-#[derive(Serialize, Deserialize, SmartDefault, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, SmartDefault, Debug, Clone, PartialEq, Eq)]
 pub struct LocalServer {
     /// Port to host local server.
     ///
     /// Default is a random free port.
     pub port: Option<u16>,
-    /// Extra arguments for invoking local server.
-    pub extra_args: Vec<String>,
+    /// Arguments passed to the LanguageTool server, i.e., after `--port`.
+    ///
+    /// `extra_args` is accepted as deprecated alias.
+    #[serde(default, alias = "extra_args")]
+    pub server_args: Vec<String>,
+    /// Environment variables set for the server, e.g., `JAVA_TOOL_OPTIONS`.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Directory of the n-gram data, enables detecting commonly confused
+    /// words.
+    ///
+    /// See <https://dev.languagetool.org/finding-errors-using-n-gram-data>.
+    pub ngram_dir: Option<PathBuf>,
+    /// Directory of the word2vec models, enables additional confusion
+    /// detection.
+    pub word2vec_dir: Option<PathBuf>,
+    /// Share the server with other instances started with the same
+    /// configuration, instead of spawning one per instance.
+    ///
+    /// The first instance spawns the server and the last one to exit shuts it
+    /// down.
+    #[serde(default)]
+    pub shared: bool,
 }
 
-#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone)]
 pub struct State {
     /// Location to put state, i.e., false positives, disabled rules
     /// and dictionary.
@@ -64,5 +292,366 @@ pub struct State {
     /// | Linux    | `$XDG_CONFIG_HOME/doc-spelling-ls/state.json` or `$HOME/.config/doc-spelling-ls/state.json` |
     /// | macOS    | `$HOME/Library/Application Support/doc-spelling-ls/state.json`                              |
     /// | Windows  | `{FOLDERID_RoamingAppData}\doc-spelling-ls/sate.json`                                       |
+    ///
+    /// If no home directory can be found, the state is only kept in memory.
     pub location: Option<PathBuf>,
+    /// Whether the default location is shared by all workspaces or
+    /// `.doc-spelling/state.json` in the workspace root.
+    #[serde(default)]
+    pub scope: Scope,
+    /// Additional dictionaries, the format is detected by extension:
+    ///
+    /// | Extension | Format                                                    |
+    /// | --------- | --------------------------------------------------------- |
+    /// | `.json`   | Array of words                                            |
+    /// | `.dic`    | Hunspell dictionary, word count and `/` flags are ignored |
+    /// | `.txt`    | One word per line                                         |
+    #[serde(default)]
+    pub dictionary_files: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// One state for all workspaces.
+    #[default]
+    Global,
+    /// One state per workspace, falls back to [`Scope::Global`] without a
+    /// workspace root.
+    Workspace,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, SmartDefault, Debug, Clone)]
+#[serde(default)]
+pub struct Diagnostics {
+    /// Number of LanguageTool results to keep cached.
+    #[default = 500]
+    pub cache_size: usize,
+    /// Maximum number of checks sent to LanguageTool at the same time, across
+    /// all documents.
+    #[default = 10]
+    pub max_concurrent_checks: usize,
+    /// Keep the cached results across sessions, in the cache directory.
+    #[default = true]
+    pub persistent_cache: bool,
+    /// Milliseconds a changed document has to stay unchanged before it is
+    /// checked, saved documents are checked immediately.
+    #[default = 300]
+    pub delay_ms: u64,
+    /// Check comments inside rust code examples, the code itself is never
+    /// checked.
+    pub code_block_comments: bool,
+    /// Also check string literals containing whitespace, e.g., messages,
+    /// format placeholders and escapes are ignored, off by default.
+    pub string_literals: bool,
+    /// Also check the spelling of the words in identifiers, split at `_` and
+    /// case changes, e.g., `parse_HTTPRequest` into `parse`, `HTTP` and
+    /// `Request`, off by default.
+    pub identifiers: bool,
+    /// Check whether list items end with a period, off by default.
+    pub list_punctuation: Option<ListPunctuation>,
+    /// Text headings are prefixed with by level, e.g., `{ "1": "" }` to check
+    /// titles without prefix, other levels use `Heading: `.
+    pub heading_prefixes: HashMap<u8, String>,
+    pub severity: Severities,
+    /// Language ids by file extension, either `rs` or `*.rs` like in
+    /// `languages`, used when the client sends no or a generic (`plaintext`)
+    /// language id.
+    #[default(_code = r#"[("rs".to_owned(), "rust".to_owned())].into()"#)]
+    pub extensions: HashMap<String, String>,
+    pub check: Check,
+}
+
+impl Diagnostics {
+    /// Checks values that cannot be expressed in the types.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.cache_size == 0 {
+            return Err("`diagnostics.cache_size` must be at least 1".to_owned());
+        }
+        if self.max_concurrent_checks == 0 {
+            return Err("`diagnostics.max_concurrent_checks` must be at least 1".to_owned());
+        }
+        Ok(())
+    }
+
+    /// `language_id` of `document`, resolved through `extensions` if it is
+    /// missing or generic (`plaintext`).
+    pub fn language_id(&self, document: &lsp_types::Url, language_id: String) -> String {
+        if !language_id.is_empty() && language_id != "plaintext" {
+            return language_id;
+        }
+        document
+            .path_segments()
+            .and_then(Iterator::last)
+            .and_then(|name| name.rsplit_once('.'))
+            .and_then(|(_, extension)| self.extension_language_id(extension))
+            .map(ToOwned::to_owned)
+            .unwrap_or(language_id)
+    }
+
+    /// Language id configured in `extensions` for `extension`, written either
+    /// with or without the leading `*.`.
+    pub fn extension_language_id(&self, extension: &str) -> Option<&str> {
+        self.extensions
+            .get(extension)
+            .or_else(|| self.extensions.get(&format!("*.{extension}")))
+            .map(String::as_str)
+    }
+}
+
+/// Parameters passed to LanguageTool.
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct Check {
+    /// Native language of the writer, e.g., `de-DE`, enables detecting false
+    /// friends.
+    pub mother_tongue: Option<String>,
+    /// Use `picky` to enable additional rules.
+    pub level: Level,
+    /// Variants to use for languages without a variant, e.g., `en-GB`.
+    pub preferred_variants: Vec<String>,
+    /// Rules to enable in addition to the default ones, e.g., rules that are
+    /// off by default.
+    pub enabled_rules: Vec<String>,
+    /// Categories to enable in addition to the default ones.
+    pub enabled_categories: Vec<String>,
+    /// Categories to disable in addition to the ones disabled with
+    /// `DisableCategory`.
+    pub disabled_categories: Vec<String>,
+    /// Check superfluous whitespace, i.e., `WHITESPACE_RULE` and
+    /// `CONSECUTIVE_SPACES`, off by default as code comments are often
+    /// aligned with spaces.
+    pub whitespace: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    #[default]
+    Default,
+    Picky,
+}
+
+/// Severity of diagnostics, by rule first, then category, then issue type.
+#[derive(Serialize, Deserialize, JsonSchema, SmartDefault, Debug, Clone)]
+#[serde(default)]
+pub struct Severities {
+    /// By LanguageTool issue type, e.g., `misspelling`, `grammar` or `style`.
+    #[default(_code = r#"[
+        ("misspelling".to_owned(), Severity::Warning),
+        ("style".to_owned(), Severity::Hint),
+    ]
+    .into()"#)]
+    pub issue_types: HashMap<String, Severity>,
+    /// By LanguageTool category id, e.g., `TYPOS` or `PUNCTUATION`.
+    pub categories: HashMap<String, Severity>,
+    /// By LanguageTool rule id, e.g., `MORFOLOGIK_RULE_EN_US`.
+    pub rules: HashMap<String, Severity>,
+    /// For everything else.
+    #[default(Severity::Information)]
+    pub default: Severity,
+}
+
+impl Severities {
+    pub fn get(&self, issue_type: &str, category: &str, rule: &str) -> Severity {
+        self.rules
+            .get(rule)
+            .or_else(|| self.categories.get(category))
+            .or_else(|| self.issue_types.get(issue_type))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl From<Severity> for lsp_types::DiagnosticSeverity {
+    fn from(severity: Severity) -> Self {
+        match severity {
+            Severity::Error => Self::ERROR,
+            Severity::Warning => Self::WARNING,
+            Severity::Information => Self::INFORMATION,
+            Severity::Hint => Self::HINT,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ListPunctuation {
+    /// Every item ends with punctuation.
+    Always,
+    /// No item ends with a period.
+    Never,
+    /// Items end like the first item of their list.
+    Consistent,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn language_id_resolves_generic_ids_from_extensions() {
+        let mut diagnostics = Diagnostics::default();
+        diagnostics
+            .extensions
+            .insert("*.md".to_owned(), "markdown".to_owned());
+        let lib = lsp_types::Url::parse("file:///project/src/lib.rs").unwrap();
+        let changelog = lsp_types::Url::parse("file:///project/CHANGELOG.md").unwrap();
+        let readme = lsp_types::Url::parse("file:///project/README").unwrap();
+        assert_eq!(diagnostics.language_id(&lib, String::new()), "rust");
+        assert_eq!(
+            diagnostics.language_id(&lib, "plaintext".to_owned()),
+            "rust"
+        );
+        assert_eq!(
+            diagnostics.language_id(&lib, "markdown".to_owned()),
+            "markdown"
+        );
+        assert_eq!(
+            diagnostics.language_id(&changelog, String::new()),
+            "markdown"
+        );
+        assert_eq!(
+            diagnostics.language_id(&readme, "plaintext".to_owned()),
+            "plaintext"
+        );
+    }
+
+    #[test]
+    fn language_prefers_extension_over_language_id() {
+        let mut config = Config::default();
+        config
+            .languages
+            .insert("*.md".to_owned(), "de-DE".to_owned());
+        config
+            .languages
+            .insert("rust".to_owned(), "en-GB".to_owned());
+        let readme = lsp_types::Url::parse("file:///project/README.md").unwrap();
+        let lib = lsp_types::Url::parse("file:///project/src/lib.rs").unwrap();
+        assert_eq!(config.language(&readme, "rust"), Some("de-DE"));
+        assert_eq!(config.language(&lib, "rust"), Some("en-GB"));
+        assert_eq!(config.language(&lib, "c"), None);
+    }
+
+    #[test]
+    fn validate_rejects_zero_limits() {
+        let error = |settings: Value| load(Some(settings), None).unwrap_err();
+        assert_eq!(
+            error(json!({ "diagnostics": { "cache_size": 0 } })),
+            "`diagnostics.cache_size` must be at least 1"
+        );
+        assert_eq!(
+            error(json!({ "diagnostics": { "max_concurrent_checks": 0 } })),
+            "`diagnostics.max_concurrent_checks` must be at least 1"
+        );
+        assert_eq!(
+            error(json!({ "server": { "type": "Online", "requests_per_minute": 0 } })),
+            "`server.requests_per_minute` must be at least 1"
+        );
+        let valid = json!({ "server": { "type": "Online", "requests_per_minute": 1 } });
+        assert!(load(Some(valid), None).is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_unsupported_language_globs() {
+        for glob in ["docs/*.md", "*.{md,txt}", "README*", "*."] {
+            assert_eq!(
+                load(Some(json!({ "languages": { glob: "de-DE" } })), None).unwrap_err(),
+                format!("`languages` only supports language ids and `*.<extension>`, not `{glob}`")
+            );
+        }
+        let valid = json!({ "languages": { "*.md": "de-DE", "markdown": "en-US" } });
+        assert!(load(Some(valid), None).is_ok());
+    }
+
+    #[test]
+    fn project_file_only_sets_project_sections() {
+        let workspace = std::env::temp_dir().join(format!(
+            "doc-spelling-lsp-project-config-{}",
+            std::process::id()
+        ));
+        let dir = workspace.join(".doc-spelling");
+        _ = fs::remove_dir_all(&workspace);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("config.toml"),
+            r#"
+            languages = { rust = "de-DE" }
+
+            [server]
+            type = "Local"
+            executable = "./run-me"
+
+            [state]
+            location = "state.json"
+            dictionary_files = ["words.txt", "/usr/share/dict/words"]
+
+            [diagnostics]
+            delay_ms = 0
+            "#,
+        )
+        .unwrap();
+        let file = project_file(&workspace);
+        assert_eq!(file, dir.join("config.toml"));
+        let config = load(None, Some(&file)).unwrap();
+        assert_eq!(config.server, Server::default());
+        assert_eq!(config.state.location, None);
+        assert_eq!(config.state.dictionary_files, [
+            dir.join("words.txt"),
+            PathBuf::from("/usr/share/dict/words")
+        ]);
+        assert_eq!(config.diagnostics.delay_ms, 0);
+        assert_eq!(config.languages["rust"], "de-DE");
+        // the editor settings can still configure everything
+        let config = load(Some(json!({ "server": { "type": "Local" } })), Some(&file)).unwrap();
+        assert!(matches!(config.server, Server::Local { .. }));
+        fs::remove_dir_all(workspace).unwrap();
+    }
+
+    /// Asserts that every field `value` serializes is a property of
+    /// `definition`.
+    #[track_caller]
+    fn assert_in_schema(value: impl Serialize, definition: &Value) {
+        let properties = definition["properties"]
+            .as_object()
+            .expect("definition has properties");
+        let value = serde_json::to_value(value).expect("value is serializable");
+        for field in value.as_object().expect("value is an object").keys() {
+            assert!(
+                properties.contains_key(field),
+                "`{field}` is missing in the schema"
+            );
+        }
+    }
+
+    #[test]
+    fn schema_contains_every_field() {
+        let schema: Value = serde_json::from_str(&schema()).expect("schema is valid json");
+        let definitions = &schema["definitions"];
+        let config = Config::default();
+        assert_in_schema(&config, &schema);
+        assert_in_schema(&config.state, &definitions["State"]);
+        assert_in_schema(&config.diagnostics, &definitions["Diagnostics"]);
+        assert_in_schema(&config.diagnostics.check, &definitions["Check"]);
+        assert_in_schema(&config.diagnostics.severity, &definitions["Severities"]);
+        let variants = definitions["Server"]["oneOf"]
+            .as_array()
+            .expect("server has variants");
+        for (variant, definition) in ["Embedded", "Online", "Local"].into_iter().zip(variants) {
+            let server: Server =
+                serde_json::from_value(json!({ "type": variant })).expect("variant is valid");
+            assert_in_schema(server, definition);
+        }
+    }
 }
@@ -1,39 +1,182 @@
 use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
 use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
 use std::time::Duration;
 
-use cached::proc_macro::cached;
+use anyhow::{bail, Context as _};
+use cached::{Cached, SizedCache};
 use futures::{StreamExt, TryStreamExt};
-use languagetool_rust::check::DataAnnotation;
+use languagetool_rust::check::{DataAnnotation, Match};
 use languagetool_rust::CheckRequest;
-use log::{debug, error};
-use lsp_types::{Diagnostic, DiagnosticSeverity, Position};
+use log::{debug, error, info, log_enabled, trace, warn, Level};
+use lsp_types::{
+    CodeDescription, Diagnostic, DiagnosticSeverity, DiagnosticTag, NumberOrString, Position,
+    PositionEncodingKind, Url,
+};
 use non_exhaustive::non_exhaustive;
-use ra_ap_rustc_lexer::{DocStyle, Token as RustToken, TokenKind as RustTokenKind};
+use pulldown_cmark::{BrokenLink, CodeBlockKind, CowStr, LinkType};
+use ra_ap_rustc_lexer::{DocStyle, LiteralKind, Token as RustToken, TokenKind as RustTokenKind};
 use serde::{Deserialize, Serialize};
-use tokio::time::sleep;
+use tokio::sync::Semaphore;
+use tokio::time::{sleep, sleep_until, Instant};
 
-use crate::state::State;
+use crate::config;
+use crate::line_index::LineIndex;
+use crate::state::{FalsePositive, State, AUTO_LANGUAGE, DEFAULT_LANGUAGE};
+
+/// Log target for the raw LanguageTool payloads.
+///
+/// These can be large, so they are only logged when explicitly enabled, e.g.,
+/// with `RUST_LOG=doc_spelling_lsp::payload=trace`.
+const PAYLOAD_LOG_TARGET: &str = "doc_spelling_lsp::payload";
+
+/// Start of an inline directive, e.g., `<!-- doc-spelling: language=de-DE -->`
+/// or `<!-- doc-spelling: disable -->`.
+pub const DIRECTIVE_PREFIX: &str = "<!-- doc-spelling:";
+
+/// Issue type of spelling matches.
+const MISSPELLING: &str = "misspelling";
+
+/// Time checks are paused after exceeding the rate limit, the window
+/// LanguageTool counts requests in.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
 
 #[derive(Clone)]
 enum Token {
     Inner(Range<usize>),
     Outer(Range<usize>),
+    Block(CommentKind, Range<usize>),
     Break,
 }
 
 #[derive(Default)]
 struct Comment {
+    kind: CommentKind,
     content: String,
     ranges: BTreeMap<usize, usize>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CommentKind {
+    /// `///` documenting the following item.
+    #[default]
+    Outer,
+    /// `//!` documenting the enclosing item.
+    Inner,
+    /// String literal, checked as plain text instead of markdown.
+    String,
+}
+
+/// Markdown elements the current event of a comment is nested in.
+#[derive(Default)]
+struct Nesting {
+    code_block: usize,
+    rust_code_block: bool,
+    link: usize,
+    autolink: bool,
+    html_code: usize,
+    html_raw: usize,
+}
+
+impl Nesting {
+    /// Annotates the `content` of `event`, after entering or leaving the
+    /// element it starts or ends.
+    fn annotation(
+        &mut self,
+        event: pulldown_cmark::Event<'_>,
+        content: String,
+        config: &config::Diagnostics,
+    ) -> DataAnnotation {
+        match event {
+            // the text of autolinks is the url or email address itself
+            pulldown_cmark::Event::Text(_) if self.autolink => {
+                DataAnnotation::new_interpreted_markup(content, "0".into())
+            }
+            pulldown_cmark::Event::Text(text) if self.link > 0 && is_rust_path(&text) => {
+                DataAnnotation::new_interpreted_markup(content, "0".into())
+            }
+            pulldown_cmark::Event::Text(_) if self.html_raw > 0 => {
+                DataAnnotation::new_markup(content)
+            }
+            pulldown_cmark::Event::Text(_) if self.html_code > 0 => {
+                DataAnnotation::new_interpreted_markup(content, "0".into())
+            }
+            pulldown_cmark::Event::Text(_) if self.code_block == 0 => {
+                DataAnnotation::new_text(content)
+            }
+            pulldown_cmark::Event::SoftBreak => {
+                DataAnnotation::new_interpreted_markup(content, " ".to_owned())
+            }
+            pulldown_cmark::Event::HardBreak => {
+                DataAnnotation::new_interpreted_markup(content, "\n\n".to_owned())
+            }
+            pulldown_cmark::Event::Code(_) => {
+                DataAnnotation::new_interpreted_markup(content, "0".into())
+            }
+            pulldown_cmark::Event::Start(pulldown_cmark::Tag::Heading { level, .. }) => {
+                match config.heading_prefixes.get(&(level as u8)) {
+                    Some(prefix) if prefix.is_empty() => DataAnnotation::new_markup(content),
+                    Some(prefix) => DataAnnotation::new_interpreted_markup(content, prefix.clone()),
+                    None => DataAnnotation::new_interpreted_markup(content, "Heading: ".into()),
+                }
+            }
+            pulldown_cmark::Event::End(
+                pulldown_cmark::TagEnd::Paragraph
+                | pulldown_cmark::TagEnd::Heading(_)
+                | pulldown_cmark::TagEnd::List(_)
+                | pulldown_cmark::TagEnd::BlockQuote
+                | pulldown_cmark::TagEnd::HtmlBlock
+                | pulldown_cmark::TagEnd::Item
+                | pulldown_cmark::TagEnd::TableHead
+                | pulldown_cmark::TagEnd::TableRow
+                | pulldown_cmark::TagEnd::TableCell
+                | pulldown_cmark::TagEnd::Image,
+            ) => DataAnnotation::new_interpreted_markup(content, "\n".into()),
+            pulldown_cmark::Event::Start(pulldown_cmark::Tag::CodeBlock(kind)) => {
+                self.code_block += 1;
+                self.rust_code_block = is_rust_code_block(&kind);
+                DataAnnotation::new_interpreted_markup(content, "\n\n".to_owned())
+            }
+            pulldown_cmark::Event::End(pulldown_cmark::TagEnd::CodeBlock) => {
+                self.code_block -= 1;
+                DataAnnotation::new_interpreted_markup(content, "\n\n".to_owned())
+            }
+            pulldown_cmark::Event::Start(pulldown_cmark::Tag::Link { link_type, .. }) => {
+                self.link += 1;
+                self.autolink = matches!(link_type, LinkType::Autolink | LinkType::Email);
+                DataAnnotation::new_markup(content)
+            }
+            pulldown_cmark::Event::End(pulldown_cmark::TagEnd::Link) => {
+                self.link -= 1;
+                self.autolink = false;
+                DataAnnotation::new_markup(content)
+            }
+            pulldown_cmark::Event::InlineHtml(html) => {
+                count_html_tag(&html, &mut self.html_code, &mut self.html_raw);
+                DataAnnotation::new_markup(content)
+            }
+            _ => DataAnnotation::new_markup(content),
+        }
+    }
+}
+
 impl Comment {
-    fn tag_markup(&self) -> Vec<DataAnnotation> {
-        let mut parser = pulldown_cmark::Parser::new(&self.content)
-            .into_offset_iter()
-            .peekable();
-        let mut in_code_block = 0;
+    fn tag_markup(&self, config: &config::Diagnostics) -> Vec<DataAnnotation> {
+        if let CommentKind::String = self.kind {
+            return string_annotations(&self.content);
+        }
+        let mut intra_doc_links = intra_doc_link;
+        let mut parser = pulldown_cmark::Parser::new_with_broken_link_callback(
+            &self.content,
+            pulldown_cmark::Options::empty(),
+            Some(&mut intra_doc_links),
+        )
+        .into_offset_iter()
+        .peekable();
+        let mut nesting = Nesting::default();
         let mut last = 0;
         let mut tokens = Vec::new();
         while let Some((event, mut range)) = parser.next() {
@@ -49,46 +192,138 @@ impl Comment {
             }
             last = range.end;
             let content = self.content[range].to_owned();
-            tokens.push(match event {
-                pulldown_cmark::Event::Text(_) if in_code_block == 0 => {
-                    DataAnnotation::new_text(content)
-                }
-                pulldown_cmark::Event::SoftBreak => {
-                    DataAnnotation::new_interpreted_markup(content, " ".to_owned())
-                }
-                pulldown_cmark::Event::HardBreak => {
-                    DataAnnotation::new_interpreted_markup(content, "\n\n".to_owned())
-                }
-                pulldown_cmark::Event::Code(_) => {
-                    DataAnnotation::new_interpreted_markup(content, "0".into())
-                }
-                pulldown_cmark::Event::Start(pulldown_cmark::Tag::Heading { .. }) => {
-                    DataAnnotation::new_interpreted_markup(content, "Heading: ".into())
+            if config.code_block_comments
+                && nesting.code_block > 0
+                && nesting.rust_code_block
+                && matches!(event, pulldown_cmark::Event::Text(_))
+            {
+                tokens.extend(rust_comments(&content));
+                continue;
+            }
+            if let pulldown_cmark::Event::Html(_) = event {
+                tokens.extend(html_annotations(
+                    &content,
+                    &mut nesting.html_code,
+                    &mut nesting.html_raw,
+                ));
+                continue;
+            }
+            tokens.push(nesting.annotation(event, content, config));
+        }
+        tokens
+    }
+
+    /// Splits the content at `language` directives, each region extends from
+    /// its directive to the next one.
+    fn language_regions<'a>(&'a self, default: &'a str) -> Vec<(&'a str, Range<usize>)> {
+        let mut regions = vec![(default, 0..self.content.len())];
+        for (start, _) in self.content.match_indices(DIRECTIVE_PREFIX) {
+            let directive = &self.content[start + DIRECTIVE_PREFIX.len()..];
+            let Some((directive, _)) = directive.split_once("-->") else {
+                continue;
+            };
+            let Some(language) = directive
+                .split_whitespace()
+                .find_map(|option| option.strip_prefix("language="))
+            else {
+                continue;
+            };
+            let last = regions.last_mut().expect("regions start non empty");
+            last.1.end = start;
+            regions.push((language, start..self.content.len()));
+        }
+        regions
+    }
+
+    /// Ranges of the content checking is disabled in, with the rules disabled,
+    /// all if empty.
+    ///
+    /// `disable` disables until the next `enable`, `disable-next-line` only
+    /// for the line after the directive, both followed by optional rule ids,
+    /// e.g., `<!-- doc-spelling: disable-next-line MORFOLOGIK_RULE_EN_US -->`.
+    fn disabled_regions(&self) -> Vec<(Range<usize>, Vec<&str>)> {
+        let mut regions = Vec::new();
+        let mut open: Option<(usize, Vec<&str>)> = None;
+        for (start, _) in self.content.match_indices(DIRECTIVE_PREFIX) {
+            let directive = &self.content[start + DIRECTIVE_PREFIX.len()..];
+            let Some((directive, _)) = directive.split_once("-->") else {
+                continue;
+            };
+            let end = start + DIRECTIVE_PREFIX.len() + directive.len() + "-->".len();
+            let mut options = directive.split_whitespace();
+            let command = options.next();
+            let rules = options.filter(|option| !option.contains('=')).collect();
+            match command {
+                Some("disable") => {
+                    if let Some((from, rules)) = open.take() {
+                        regions.push((from..start, rules));
+                    }
+                    open = Some((end, rules));
                 }
-                pulldown_cmark::Event::End(
-                    pulldown_cmark::TagEnd::Paragraph
-                    | pulldown_cmark::TagEnd::Heading(_)
-                    | pulldown_cmark::TagEnd::List(_)
-                    | pulldown_cmark::TagEnd::BlockQuote
-                    | pulldown_cmark::TagEnd::HtmlBlock
-                    | pulldown_cmark::TagEnd::Item
-                    | pulldown_cmark::TagEnd::TableHead
-                    | pulldown_cmark::TagEnd::TableRow
-                    | pulldown_cmark::TagEnd::TableCell
-                    | pulldown_cmark::TagEnd::Image,
-                ) => DataAnnotation::new_interpreted_markup(content, "\n".into()),
-                pulldown_cmark::Event::Start(pulldown_cmark::Tag::CodeBlock(_)) => {
-                    in_code_block += 1;
-                    DataAnnotation::new_interpreted_markup(content, "\n\n".to_owned())
+                Some("enable") => {
+                    if let Some((from, rules)) = open.take() {
+                        regions.push((from..start, rules));
+                    }
                 }
-                pulldown_cmark::Event::End(pulldown_cmark::TagEnd::CodeBlock) => {
-                    in_code_block -= 1;
-                    DataAnnotation::new_interpreted_markup(content, "\n\n".to_owned())
+                Some("disable-next-line") => {
+                    let line_end = |from: usize| {
+                        self.content[from..]
+                            .find('\n')
+                            .map_or(self.content.len(), |idx| from + idx)
+                    };
+                    let next_line = (line_end(end) + 1).min(self.content.len());
+                    regions.push((next_line..line_end(next_line), rules));
                 }
-                _ => DataAnnotation::new_markup(content),
+                _ => {}
+            }
+        }
+        if let Some((from, rules)) = open {
+            regions.push((from..self.content.len(), rules));
+        }
+        regions
+    }
+
+    /// Tags the markup for each language in the comment, text in other
+    /// languages is treated as markup.
+    fn language_annotations<'a>(
+        &'a self,
+        config: &config::Diagnostics,
+        default: &'a str,
+    ) -> BTreeMap<&'a str, Vec<DataAnnotation>> {
+        let annotations = self.tag_markup(config);
+        let regions = self.language_regions(default);
+        if let [(language, _)] = regions[..] {
+            return [(language, annotations)].into();
+        }
+        let mut by_language: BTreeMap<_, _> = regions
+            .iter()
+            .map(|(language, _)| (*language, Vec::with_capacity(annotations.len())))
+            .collect();
+        let mut offset = 0;
+        for annotation in annotations {
+            let len = annotation
+                .text
+                .as_ref()
+                .or(annotation.markup.as_ref())
+                .map_or(0, String::len);
+            // directives are html and therefore never part of a text annotation
+            let own = annotation.text.as_ref().and_then(|_| {
+                regions
+                    .iter()
+                    .find(|(_, range)| range.contains(&offset))
+                    .map(|(language, _)| *language)
             });
+            for (language, annotations) in &mut by_language {
+                annotations.push(match (&annotation.text, own) {
+                    (Some(text), Some(own)) if own != *language => {
+                        DataAnnotation::new_markup(text.clone())
+                    }
+                    _ => annotation.clone(),
+                });
+            }
+            offset += len;
         }
-        tokens
+        by_language
     }
 
     fn push(&mut self, document: &str, range: Range<usize>) {
@@ -98,41 +333,454 @@ impl Comment {
         self.content.push('\n');
     }
 
-    fn map_position(&self, document: &str, offset: usize) -> Position {
+    fn map_position(&self, index: &LineIndex, offset: usize) -> Position {
         let mapping = self
             .ranges
             .range(..=offset)
             .last()
             .unwrap_or(self.ranges.first_key_value().unwrap());
-        let offset = mapping.1 + (offset - mapping.0);
+        index.position(mapping.1 + (offset - mapping.0))
+    }
 
-        let line = (document[..offset].lines().count() - 1).try_into().unwrap();
-        let character = document[..offset]
-            .rsplit_once('\n')
-            .map_or(offset, |(_, r)| r.len())
-            .try_into()
-            .unwrap();
+    /// Maps a range of the content, the end is clamped to the line containing
+    /// the start, as consecutive lines are not contiguous in the document.
+    fn map_range(&self, index: &LineIndex, range: Range<usize>) -> lsp_types::Range {
+        let line_end = self
+            .ranges
+            .range(range.start + 1..)
+            .next()
+            .map_or(self.content.len(), |(&next, _)| next)
+            // exclude the `\n` appended in `push`
+            .saturating_sub(1)
+            .max(range.start);
+        lsp_types::Range {
+            start: self.map_position(index, range.start),
+            end: self.map_position(index, range.end.min(line_end)),
+        }
+    }
 
-        Position { line, character }
+    /// Whether the document `offset` is inside the content or at the end of a
+    /// line of it.
+    fn contains(&self, offset: usize) -> bool {
+        let mut lines = self.ranges.iter().peekable();
+        while let Some((&content_start, &start)) = lines.next() {
+            // the `\n` appended in `push` is not part of the document
+            let len =
+                lines.peek().map_or(self.content.len(), |(&next, _)| next) - content_start - 1;
+            if (start..=start + len).contains(&offset) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Range of the comment content in the document.
+    fn document_range(&self, index: &LineIndex) -> lsp_types::Range {
+        lsp_types::Range {
+            start: self.map_position(index, 0),
+            // the last character is the `\n` appended in `push`
+            end: self.map_position(index, self.content.len() - 1),
+        }
     }
 }
 
+/// Replaces `range` of `document`, with positions in `encoding`, by `text`.
+pub fn apply_change(
+    document: &mut String,
+    encoding: &PositionEncodingKind,
+    range: lsp_types::Range,
+    text: &str,
+) {
+    let index = LineIndex::new(document, encoding);
+    let start = index.offset(range.start);
+    let end = index.offset(range.end).max(start);
+    document.replace_range(start..end, text);
+}
+
+/// Converts an offset in UTF-16 code units, as returned by LanguageTool, to a
+/// byte offset into `text`.
+fn byte_offset(text: &str, utf16_offset: usize) -> usize {
+    let mut units = 0;
+    for (idx, c) in text.char_indices() {
+        if units >= utf16_offset {
+            return idx;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+/// Annotates everything but the comments in `code` as markup.
+fn rust_comments(code: &str) -> Vec<DataAnnotation> {
+    let mut annotations = Vec::new();
+    let mut offset = 0;
+    for RustToken { kind, len } in ra_ap_rustc_lexer::tokenize(code) {
+        let token = &code[offset..offset + len as usize];
+        offset += len as usize;
+        let (start, end) = match kind {
+            RustTokenKind::LineComment { doc_style } => {
+                (if doc_style.is_some() { 3 } else { 2 }, token.len())
+            }
+            RustTokenKind::BlockComment {
+                doc_style,
+                terminated: true,
+            } => (if doc_style.is_some() { 3 } else { 2 }, token.len() - 2),
+            _ => {
+                annotations.push(DataAnnotation::new_markup(token.to_owned()));
+                continue;
+            }
+        };
+        let start = start.min(end);
+        annotations.push(DataAnnotation::new_markup(token[..start].to_owned()));
+        annotations.push(DataAnnotation::new_text(token[start..end].to_owned()));
+        if end < token.len() {
+            annotations.push(DataAnnotation::new_markup(token[end..].to_owned()));
+        }
+    }
+    annotations
+}
+
+/// Whether rustdoc would treat the code block as rust, i.e., it is indented or
+/// the fence only contains `rust` and rustdoc attributes.
+fn is_rust_code_block(kind: &CodeBlockKind) -> bool {
+    const RUSTDOC_ATTRIBUTES: [&str; 6] = [
+        "ignore",
+        "should_panic",
+        "no_run",
+        "compile_fail",
+        "test_harness",
+        "standalone_crate",
+    ];
+    match kind {
+        CodeBlockKind::Indented => true,
+        CodeBlockKind::Fenced(info) => info
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|token| !token.is_empty())
+            .all(|token| {
+                token == "rust"
+                    || token.starts_with("edition")
+                    || RUSTDOC_ATTRIBUTES.contains(&token)
+            }),
+    }
+}
+
+/// Html tags whose content is code, checked like inline code.
+const HTML_CODE_TAGS: [&str; 5] = ["code", "kbd", "pre", "samp", "var"];
+/// Html tags whose content is not meant to be read at all.
+const HTML_RAW_TAGS: [&str; 2] = ["script", "style"];
+/// Html tags separating their content from the surrounding text.
+const HTML_BLOCK_TAGS: [&str; 10] = ["br", "dd", "div", "dt", "hr", "li", "p", "td", "th", "tr"];
+
+/// Whether `html` opens (`true`) or closes (`false`) one of `tags`, e.g.,
+/// `<code>` or `</kbd>`.
+fn html_tag(html: &str, tags: &[&str]) -> Option<bool> {
+    let tag = html.strip_prefix('<')?;
+    let (opening, tag) = match tag.strip_prefix('/') {
+        Some(tag) => (false, tag),
+        None => (true, tag),
+    };
+    let name = tag
+        .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .next()?;
+    (tags.iter().any(|tag| tag.eq_ignore_ascii_case(name)) && !tag.ends_with("/>"))
+        .then_some(opening)
+}
+
+/// Tracks how many code and raw tags are open after the tag `html`.
+fn count_html_tag(html: &str, in_html_code: &mut usize, in_html_raw: &mut usize) {
+    for (tags, open) in [
+        (&HTML_CODE_TAGS[..], in_html_code),
+        (&HTML_RAW_TAGS[..], in_html_raw),
+    ] {
+        match html_tag(html, tags) {
+            Some(true) => *open += 1,
+            Some(false) => *open = open.saturating_sub(1),
+            None => {}
+        }
+    }
+}
+
+/// Annotates a line of an html block, tags and comments are markup, the
+/// content of code tags is interpreted like inline code, the content of
+/// `<script>` and `<style>` is skipped and entities are decoded.
+fn html_annotations(
+    html: &str,
+    in_html_code: &mut usize,
+    in_html_raw: &mut usize,
+) -> Vec<DataAnnotation> {
+    let mut annotations = Vec::new();
+    let mut rest = html;
+    while !rest.is_empty() {
+        let len = if rest.starts_with("<!--") {
+            let len = rest.find("-->").map_or(rest.len(), |end| end + "-->".len());
+            annotations.push(DataAnnotation::new_markup(rest[..len].to_owned()));
+            len
+        } else if rest.starts_with('<') {
+            let len = rest.find('>').map_or(rest.len(), |end| end + 1);
+            let tag = &rest[..len];
+            count_html_tag(tag, in_html_code, in_html_raw);
+            annotations.push(if html_tag(tag, &HTML_BLOCK_TAGS).is_some() {
+                DataAnnotation::new_interpreted_markup(tag.to_owned(), "\n".into())
+            } else {
+                DataAnnotation::new_markup(tag.to_owned())
+            });
+            len
+        } else if let Some((len, decoded)) = html_entity(rest).filter(|_| *in_html_raw == 0) {
+            annotations.push(DataAnnotation::new_interpreted_markup(
+                rest[..len].to_owned(),
+                decoded.to_string(),
+            ));
+            len
+        } else {
+            // at least one character, e.g., a `&` that is no entity
+            let first = rest.chars().next().map_or(1, char::len_utf8);
+            let len = rest[first..]
+                .find(['<', '&'])
+                .map_or(rest.len(), |end| end + first);
+            let text = rest[..len].to_owned();
+            annotations.push(if *in_html_raw > 0 {
+                DataAnnotation::new_markup(text)
+            } else if *in_html_code > 0 {
+                DataAnnotation::new_interpreted_markup(text, "0".into())
+            } else {
+                DataAnnotation::new_text(text)
+            });
+            len
+        };
+        rest = &rest[len..];
+    }
+    annotations
+}
+
+/// Decodes the html entity `text` starts with, e.g., `&amp;` or `&#x2014;`,
+/// returning its length and the character.
+fn html_entity(text: &str) -> Option<(usize, char)> {
+    let (name, _) = text.strip_prefix('&')?.split_once(';')?;
+    if name.len() > 10 {
+        return None;
+    }
+    let decoded = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        _ => {
+            let code = name.strip_prefix('#')?;
+            char::from_u32(match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            })?
+        }
+    };
+    Some((name.len() + "&;".len(), decoded))
+}
+
+/// Resolves all broken links, like rustdoc does for intra-doc links, e.g.,
+/// ``[`Foo`]``, so their brackets are treated as markup.
+fn intra_doc_link(link: BrokenLink<'_>) -> Option<(CowStr<'_>, CowStr<'_>)> {
+    Some((link.reference, "".into()))
+}
+
+/// Whether link text is more likely a rust path than prose, e.g., `crate::Foo`,
+/// `HashMap` or `foo_bar`.
+fn is_rust_path(text: &str) -> bool {
+    !text.is_empty()
+        && !text.contains(char::is_whitespace)
+        && (text.contains("::")
+            || text.contains('_')
+            || text.chars().skip(1).any(char::is_uppercase))
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Meta {
     pub missspelled: Option<String>,
     pub replacements: Vec<String>,
     pub rule: Option<String>,
+    pub category: Option<Category>,
+    /// Language id of the document, e.g., `rust`.
+    pub language_id: Option<String>,
+    /// Language the text was checked in, e.g., `en-US`.
+    pub language: Option<String>,
+    pub comment_kind: Option<CommentKind>,
+    /// Identifies this occurrence, to dismiss it as false positive.
+    pub false_positive: Option<FalsePositive>,
+    /// Description of the LanguageTool rule.
+    pub description: Option<String>,
+    /// Links to explanations of the rule.
+    #[serde(default)]
+    pub urls: Vec<String>,
+    /// Identifier containing the misspelled word, the replacements are
+    /// renamed identifiers.
+    pub identifier: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Category {
+    pub id: String,
+    pub name: String,
 }
 
-#[allow(clippy::too_many_lines)]
+/// Diagnoses all doc comments in `document`, with positions in `encoding`.
 pub async fn diagnose(
     document: &str,
-    ltex_client: &languagetool_rust::ServerClient,
+    language_id: &str,
+    encoding: &PositionEncodingKind,
+    checker: &Checker,
+    state: &State,
+    config: &config::Diagnostics,
+) -> anyhow::Result<Vec<Diagnostic>> {
+    diagnose_with_progress(
+        document,
+        language_id,
+        encoding,
+        checker,
+        state,
+        config,
+        |_, _| {},
+    )
+    .await
+}
+
+/// Like [`diagnose`], reporting the number of checked and total comments to
+/// `progress` after each comment.
+pub async fn diagnose_with_progress(
+    document: &str,
+    language_id: &str,
+    encoding: &PositionEncodingKind,
+    checker: &Checker,
+    state: &State,
+    config: &config::Diagnostics,
+    progress: impl Fn(usize, usize),
+) -> anyhow::Result<Vec<Diagnostic>> {
+    let index = LineIndex::new(document, encoding);
+    let mut diagnostics = diagnose_comments(
+        checked_comments(document, config),
+        language_id,
+        &index,
+        checker,
+        state,
+        config,
+        progress,
+    )
+    .await?;
+    if config.identifiers {
+        diagnostics.extend(
+            diagnose_identifiers(document, language_id, &index, checker, state, config).await?,
+        );
+    }
+    Ok(diagnostics)
+}
+
+/// Only diagnoses the comments overlapping `range`.
+pub async fn diagnose_range(
+    document: &str,
+    language_id: &str,
+    encoding: &PositionEncodingKind,
+    range: lsp_types::Range,
+    checker: &Checker,
     state: &State,
+    config: &config::Diagnostics,
 ) -> anyhow::Result<Vec<Diagnostic>> {
+    let index = LineIndex::new(document, encoding);
+    let comments = checked_comments(document, config)
+        .into_iter()
+        .filter(|comment| {
+            let lsp_types::Range { start, end } = comment.document_range(&index);
+            start <= range.end && range.start <= end
+        })
+        .collect();
+    let mut diagnostics = diagnose_comments(
+        comments,
+        language_id,
+        &index,
+        checker,
+        state,
+        config,
+        |_, _| {},
+    )
+    .await?;
+    if config.identifiers {
+        diagnostics.extend(
+            diagnose_identifiers(document, language_id, &index, checker, state, config)
+                .await?
+                .into_iter()
+                .filter(|diagnostic| {
+                    diagnostic.range.start <= range.end && range.start <= diagnostic.range.end
+                }),
+        );
+    }
+    Ok(diagnostics)
+}
+
+/// Text of the line before `position`, if it is inside a doc comment.
+pub fn comment_line_prefix<'a>(
+    document: &'a str,
+    encoding: &PositionEncodingKind,
+    position: Position,
+) -> Option<&'a str> {
+    let offset = LineIndex::new(document, encoding).offset(position);
+    if !doc_comments(document)
+        .iter()
+        .any(|comment| comment.contains(offset))
+    {
+        return None;
+    }
+    let line_start = document[..offset].rfind('\n').map_or(0, |idx| idx + 1);
+    Some(&document[line_start..offset])
+}
+
+/// Ranges of all occurrences of `word` as a whole word in the text of doc
+/// comments, i.e., not in code or markup.
+pub fn occurrences(
+    document: &str,
+    encoding: &PositionEncodingKind,
+    word: &str,
+    config: &config::Diagnostics,
+) -> Vec<lsp_types::Range> {
+    let index = LineIndex::new(document, encoding);
+    let mut ranges = Vec::new();
+    for comment in doc_comments(document) {
+        let mut offset = 0;
+        for annotation in comment.tag_markup(config) {
+            if let Some(text) = &annotation.text {
+                ranges.extend(word_occurrences(text, word).map(|start| {
+                    let start = offset + start;
+                    comment.map_range(&index, start..start + word.len())
+                }));
+            }
+            offset += annotation
+                .text
+                .as_ref()
+                .or(annotation.markup.as_ref())
+                .map_or(0, String::len);
+        }
+    }
+    ranges
+}
+
+/// Start of each occurrence of `word` in `text` that is not part of a longer
+/// word.
+fn word_occurrences<'a>(text: &'a str, word: &'a str) -> impl Iterator<Item = usize> + 'a {
+    text.match_indices(word)
+        .map(|(start, _)| start)
+        .filter(move |&start| {
+            !text[..start]
+                .chars()
+                .next_back()
+                .is_some_and(char::is_alphanumeric)
+                && !text[start + word.len()..]
+                    .chars()
+                    .next()
+                    .is_some_and(char::is_alphanumeric)
+        })
+}
+
+fn doc_comments(document: &str) -> Vec<Comment> {
     let mut current = 0;
     // First collect all the ranges that represent comment content
-    let doc_comments = ra_ap_rustc_lexer::tokenize(document)
+    ra_ap_rustc_lexer::tokenize(document)
         .filter_map(|RustToken { kind, len }| {
             let start = current as usize;
             let end = current + len;
@@ -142,19 +790,25 @@ pub async fn diagnose(
                 RustTokenKind::LineComment {
                     doc_style: Some(DocStyle::Inner),
                 } => Some(Token::Inner(
-                    (start + 3 + usize::from(document[3.min(end)..].starts_with(' '))).min(end)
+                    (start + 3 + usize::from(document[start + 3..end].starts_with(' '))).min(end)
                         ..end,
                 )),
                 RustTokenKind::LineComment {
                     doc_style: Some(DocStyle::Outer),
                 } => Some(Token::Outer(
-                    (start + 3 + usize::from(document[3.min(end)..].starts_with(' '))).min(end)
+                    (start + 3 + usize::from(document[start + 3..end].starts_with(' '))).min(end)
                         ..end,
                 )),
                 RustTokenKind::BlockComment {
-                    doc_style: Some(DocStyle::Inner | DocStyle::Outer),
+                    doc_style: Some(doc_style),
                     ..
-                } => todo!("parse block comments"),
+                } => Some(Token::Block(
+                    match doc_style {
+                        DocStyle::Inner => CommentKind::Inner,
+                        DocStyle::Outer => CommentKind::Outer,
+                    },
+                    start..end,
+                )),
                 RustTokenKind::Whitespace => None,
                 _ => Some(Token::Break),
             }
@@ -165,10 +819,23 @@ pub async fn diagnose(
                 let (current, range) = match (&last, c.clone()) {
                     (Token::Inner(_), Token::Inner(range))
                     | (Token::Outer(_), Token::Outer(range)) => (b.last_mut().unwrap(), range),
-                    (_, Token::Inner(range) | Token::Outer(range)) => {
+                    (_, Token::Inner(range)) => {
+                        b.push(Comment {
+                            kind: CommentKind::Inner,
+                            ..Comment::default()
+                        });
+                        (b.last_mut().unwrap(), range)
+                    }
+                    (_, Token::Outer(range)) => {
                         b.push(Comment::default());
                         (b.last_mut().unwrap(), range)
                     }
+                    // never continued by line comments
+                    (_, Token::Block(kind, range)) => {
+                        b.push(block_comment(document, kind, range));
+                        last = Token::Break;
+                        return b;
+                    }
                     _ => {
                         last = c;
                         return b;
@@ -179,113 +846,1745 @@ pub async fn diagnose(
                 last = c;
                 b
             }
-        });
+        })
+}
+
+/// Block doc comment `/** … */` or `/*! … */` without the delimiters and the
+/// `*` starting each line, if every line has one, or else their common
+/// indentation.
+fn block_comment(document: &str, kind: CommentKind, range: Range<usize>) -> Comment {
+    // after `/**` or `/*!`
+    let start = range.start + 3;
+    let text = &document[start..range.end];
+    // unterminated at the end of the document
+    let text = text
+        .strip_suffix("*/")
+        .unwrap_or(text)
+        .trim_end_matches([' ', '\t']);
+    let mut lines = Vec::new();
+    let mut offset = start;
+    for line in text.split('\n') {
+        lines.push((offset, line));
+        offset += line.len() + 1;
+    }
+    // the delimiters are usually on lines of their own
+    if lines.len() > 1 && lines[lines.len() - 1].1.trim().is_empty() {
+        lines.pop();
+    }
+    if lines.len() > 1 && lines[0].1.trim().is_empty() {
+        lines.remove(0);
+    }
+    let indentation = |line: &str| line.len() - line.trim_start_matches([' ', '\t']).len();
+    // the line of the opening delimiter is not indented
+    let following = || {
+        lines
+            .iter()
+            .filter(|&&(offset, line)| offset != start && !line.trim().is_empty())
+            .map(|&(_, line)| line)
+    };
+    let gutter = following().all(|line| line.trim_start().starts_with('*'));
+    let common_indentation = following().map(indentation).min().unwrap_or_default();
+    let mut comment = Comment {
+        kind,
+        ..Comment::default()
+    };
+    for &(offset, line) in &lines {
+        let skipped = if offset == start {
+            indentation(line)
+        } else if gutter {
+            line.trim_start_matches([' ', '\t'])
+                .strip_prefix('*')
+                .map_or(line.len(), |rest| {
+                    line.len() - rest.len() + usize::from(rest.starts_with(' '))
+                })
+        } else {
+            common_indentation.min(indentation(line))
+        };
+        comment.push(document, offset + skipped..offset + line.len());
+    }
+    comment
+}
+
+/// Doc comments and, if enabled, string literals of `document`.
+fn checked_comments(document: &str, config: &config::Diagnostics) -> Vec<Comment> {
+    let mut comments = doc_comments(document);
+    if config.string_literals {
+        comments.extend(string_literals(document));
+    }
+    comments
+}
+
+/// String literals containing whitespace, i.e., likely prose like messages
+/// instead of keys or identifiers.
+fn string_literals(document: &str) -> Vec<Comment> {
+    let mut current = 0;
+    ra_ap_rustc_lexer::tokenize(document)
+        .filter_map(|RustToken { kind, len }| {
+            let start = current as usize;
+            current += len;
+            let RustTokenKind::Literal {
+                kind: LiteralKind::Str { terminated: true },
+                suffix_start,
+            } = kind
+            else {
+                return None;
+            };
+            // without the quotes
+            let range = start + 1..start + suffix_start as usize - 1;
+            document[range.clone()]
+                .contains(char::is_whitespace)
+                .then(|| {
+                    let mut comment = Comment {
+                        kind: CommentKind::String,
+                        ..Comment::default()
+                    };
+                    comment.push(document, range);
+                    comment
+                })
+        })
+        .collect()
+}
+
+/// Annotates the content of a string literal, format placeholders, e.g.,
+/// `{name}` or `%s`, are interpreted like inline code and escapes as the
+/// character they encode.
+fn string_annotations(content: &str) -> Vec<DataAnnotation> {
+    let mut annotations = Vec::new();
+    let mut rest = content;
+    while !rest.is_empty() {
+        let escaped = |escaped: &str, interpreted: &str| {
+            rest.starts_with(escaped).then(|| {
+                (
+                    escaped.len(),
+                    DataAnnotation::new_interpreted_markup(escaped.into(), interpreted.into()),
+                )
+            })
+        };
+        let (len, annotation) = escaped("{{", "{")
+            .or_else(|| escaped("}}", "}"))
+            .or_else(|| escaped("\\n", "\n"))
+            .or_else(|| escaped("\\t", " "))
+            .or_else(|| escaped("\\\"", "\""))
+            .or_else(|| escaped("\\'", "'"))
+            .or_else(|| escaped("\\\\", "\\"))
+            .or_else(|| {
+                // `{}`, `{0}`, `{name:?}`, ...
+                let end = rest.strip_prefix('{')?.find(['{', '}'])?;
+                let len = end + "{}".len();
+                (rest.as_bytes().get(len - 1) == Some(&b'}')).then(|| {
+                    (
+                        len,
+                        DataAnnotation::new_interpreted_markup(rest[..len].into(), "0".into()),
+                    )
+                })
+            })
+            .or_else(|| {
+                // `%s`, `%d`, ...
+                let specifier = rest.strip_prefix('%')?.chars().next()?;
+                specifier.is_ascii_alphabetic().then(|| {
+                    (
+                        2,
+                        DataAnnotation::new_interpreted_markup(rest[..2].into(), "0".into()),
+                    )
+                })
+            })
+            .or_else(|| {
+                // line continuation, skipping the leading whitespace of the next line
+                let continued = rest.strip_prefix("\\\n")?;
+                let len = rest.len() - continued.trim_start().len();
+                Some((
+                    len,
+                    DataAnnotation::new_interpreted_markup(rest[..len].into(), " ".into()),
+                ))
+            })
+            .unwrap_or_else(|| {
+                // at least one character, e.g., a `{` that is no placeholder
+                let first = rest.chars().next().map_or(1, char::len_utf8);
+                let len = rest[first..]
+                    .find(['{', '}', '\\', '%'])
+                    .map_or(rest.len(), |end| end + first);
+                (len, DataAnnotation::new_text(rest[..len].into()))
+            });
+        annotations.push(annotation);
+        rest = &rest[len..];
+    }
+    annotations
+}
+
+/// Strict, reserved and weak keywords and primitive types, which the lexer
+/// reports as identifiers, `macro_rules` consists of words anyway.
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while", "abstract", "become", "box", "do", "final", "gen", "macro",
+    "override", "priv", "try", "typeof", "unsized", "virtual", "yield", "raw", "safe", "union",
+    "bool", "char", "isize", "str", "usize",
+];
+
+/// Keywords followed by the name of the item or binding they define.
+const DEFINING_KEYWORDS: [&str; 11] = [
+    "const", "enum", "fn", "let", "mod", "mut", "static", "struct", "trait", "type", "union",
+];
+
+/// Words in identifiers shorter than this are likely abbreviations.
+const MIN_IDENTIFIER_WORD_LEN: usize = 3;
+
+/// Kinds and ranges of the tokens in `document`, excluding whitespace and
+/// comments.
+fn tokens(document: &str) -> impl Iterator<Item = (RustTokenKind, Range<usize>)> + '_ {
+    let mut current = 0;
+    ra_ap_rustc_lexer::tokenize(document).filter_map(move |RustToken { kind, len }| {
+        let start = current as usize;
+        current += len;
+        let trivia = matches!(
+            kind,
+            RustTokenKind::Whitespace
+                | RustTokenKind::LineComment { .. }
+                | RustTokenKind::BlockComment { .. }
+        );
+        (!trivia).then_some((kind, start..current as usize))
+    })
+}
+
+fn is_identifier(document: &str, (kind, range): &(RustTokenKind, Range<usize>)) -> bool {
+    matches!(kind, RustTokenKind::Ident) && !RUST_KEYWORDS.contains(&&document[range.clone()])
+}
+
+/// Ranges of the identifiers in `document`, excluding keywords.
+fn identifiers(document: &str) -> impl Iterator<Item = Range<usize>> + '_ {
+    tokens(document)
+        .filter(|token| is_identifier(document, token))
+        .map(|(_, range)| range)
+}
+
+/// Ranges of the identifiers defined in `document`, the names following a
+/// defining keyword and the names of fields, parameters and generics followed
+/// by their type or bounds.
+///
+/// Other identifiers are mostly defined elsewhere, so they can't be renamed
+/// here.
+fn definitions(document: &str) -> Vec<Range<usize>> {
+    let tokens: Vec<_> = tokens(document).collect();
+    let kind = |idx: usize| tokens.get(idx).map(|(kind, _)| *kind);
+    let text = |idx: usize| &document[tokens[idx].1.clone()];
+    (0..tokens.len())
+        .filter(|&idx| is_identifier(document, &tokens[idx]))
+        .filter(|&idx| {
+            let Some(previous) = idx.checked_sub(1) else {
+                return false;
+            };
+            if DEFINING_KEYWORDS.contains(&text(previous)) {
+                return true;
+            }
+            // `name: Type`, but not `name::path`
+            let typed = kind(idx + 1) == Some(RustTokenKind::Colon)
+                && kind(idx + 2) != Some(RustTokenKind::Colon);
+            typed
+                && (text(previous) == "pub"
+                    || matches!(
+                        tokens[previous].0,
+                        RustTokenKind::OpenParen
+                            | RustTokenKind::OpenBrace
+                            | RustTokenKind::CloseParen
+                            | RustTokenKind::CloseBracket
+                            | RustTokenKind::Comma
+                            | RustTokenKind::Lt
+                    ))
+        })
+        .map(|idx| tokens[idx].1.clone())
+        .collect()
+}
+
+/// Ranges of the words of `identifier`, split at non-letters and case changes,
+/// e.g., `parseHTTPRequest` into `parse`, `HTTP` and `Request`.
+fn identifier_words(identifier: &str) -> Vec<Range<usize>> {
+    let chars: Vec<_> = identifier.char_indices().collect();
+    let mut words = Vec::new();
+    let mut start = None;
+    for (idx, &(offset, c)) in chars.iter().enumerate() {
+        if !c.is_alphabetic() {
+            if let Some(start) = start.take() {
+                words.push(start..offset);
+            }
+            continue;
+        }
+        let Some(word_start) = start else {
+            start = Some(offset);
+            continue;
+        };
+        let previous = chars[idx - 1].1;
+        let next_lowercase = chars
+            .get(idx + 1)
+            .is_some_and(|&(_, next)| next.is_lowercase());
+        // `aB`, or the start of a word following an acronym, `ABc`
+        if c.is_uppercase() && (previous.is_lowercase() || next_lowercase) {
+            words.push(word_start..offset);
+            start = Some(offset);
+        }
+    }
+    words.extend(start.map(|start| start..identifier.len()));
+    words
+}
+
+/// `replacement` capitalized like `word`.
+fn match_case(replacement: &str, word: &str) -> String {
+    let lowercase = replacement.to_lowercase();
+    if word.starts_with(char::is_uppercase) {
+        let mut chars = lowercase.chars();
+        chars
+            .next()
+            .map(|first| first.to_uppercase().chain(chars).collect())
+            .unwrap_or_default()
+    } else {
+        lowercase
+    }
+}
+
+/// Ranges of all occurrences of the identifier `name` in `document`.
+pub fn identifier_occurrences(
+    document: &str,
+    encoding: &PositionEncodingKind,
+    name: &str,
+) -> Vec<lsp_types::Range> {
+    let index = LineIndex::new(document, encoding);
+    identifiers(document)
+        .filter(|range| &document[range.clone()] == name)
+        .map(|range| lsp_types::Range {
+            start: index.position(range.start),
+            end: index.position(range.end),
+        })
+        .collect()
+}
+
+/// Ranges of the identifiers defined in `document` containing each checked
+/// word and of the word itself, by the word.
+fn identifier_words_by_text(document: &str) -> BTreeMap<&str, Vec<(Range<usize>, Range<usize>)>> {
+    let mut occurrences: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for identifier in definitions(document) {
+        for word in identifier_words(&document[identifier.clone()]) {
+            let word = identifier.start + word.start..identifier.start + word.end;
+            let text = &document[word.clone()];
+            if text.chars().count() >= MIN_IDENTIFIER_WORD_LEN && text.contains(char::is_lowercase)
+            {
+                occurrences
+                    .entry(text)
+                    .or_default()
+                    .push((identifier.clone(), word));
+            }
+        }
+    }
+    occurrences
+}
+
+/// Checks the spelling of the words in identifiers, other matches are ignored
+/// as identifiers are no sentences.
+///
+/// Only identifiers defined in the document are checked, each distinct word
+/// once, acronyms and short words are skipped.
+async fn diagnose_identifiers(
+    document: &str,
+    language_id: &str,
+    index: &LineIndex<'_>,
+    checker: &Checker,
+    state: &State,
+    config: &config::Diagnostics,
+) -> anyhow::Result<Vec<Diagnostic>> {
+    let occurrences = identifier_words_by_text(document);
+    if occurrences.is_empty() {
+        return Ok(Vec::new());
+    }
+    // one word per paragraph, so they are not checked as sentence
+    const SEPARATOR: &str = "\n\n";
+    let mut starts = BTreeMap::new();
+    let mut text = String::new();
+    for &word in occurrences.keys() {
+        starts.insert(text.len(), word);
+        text.push_str(word);
+        text.push_str(SEPARATOR);
+    }
+    let (checked, results) = checker
+        .check(
+            vec![DataAnnotation::new_text(text.clone())],
+            &state.language,
+            &config.check,
+            &state.disabled_rules,
+            &state.disabled_categories,
+        )
+        .await?;
+    let language = if state.language == AUTO_LANGUAGE {
+        checked
+    } else {
+        state.language.clone()
+    };
+    let mut diagnostics = Vec::new();
+    for result in results {
+        if result.rule.issue_type != MISSPELLING {
+            continue;
+        }
+        let offset = byte_offset(&text, result.offset);
+        let Some(&word) = starts.get(&offset) else {
+            continue;
+        };
+        // only part of the word
+        if byte_offset(&text[offset..], result.length) != word.len() {
+            continue;
+        }
+        let replacements: Vec<_> = result
+            .replacements
+            .iter()
+            .filter(|r| !r.value.is_empty() && r.value.chars().all(char::is_alphabetic))
+            .take(10)
+            .map(|r| match_case(&r.value, word))
+            .collect();
+        for (identifier, range) in &occurrences[word] {
+            let name = &document[identifier.clone()];
+            let false_positive = FalsePositive {
+                rule: result.rule.id.clone(),
+                text: word.to_owned(),
+                sentence: name.to_owned(),
+            };
+            if is_ignored(state, &language, &result, &false_positive) {
+                continue;
+            }
+            let (prefix, suffix) = (
+                &document[identifier.start..range.start],
+                &document[range.end..identifier.end],
+            );
+            let meta = Meta {
+                missspelled: Some(word.to_owned()),
+                replacements: replacements
+                    .iter()
+                    .map(|replacement| format!("{prefix}{replacement}{suffix}"))
+                    .collect(),
+                rule: None,
+                category: None,
+                language_id: Some(language_id.to_owned()),
+                language: Some(language.clone()),
+                comment_kind: None,
+                false_positive: Some(false_positive),
+                description: Some(result.rule.description.clone()),
+                urls: Vec::new(),
+                identifier: Some(name.to_owned()),
+            };
+            diagnostics.push(match_diagnostic(
+                &result,
+                lsp_types::Range {
+                    start: index.position(identifier.start),
+                    end: index.position(identifier.end),
+                },
+                format!("`{word}` in `{name}`: {}", result.message),
+                meta,
+                config,
+            ));
+        }
+    }
+    Ok(diagnostics)
+}
 
-    futures::stream::iter(doc_comments)
-        .map(|c| diagnose_comment(c, document, ltex_client, state))
+async fn diagnose_comments(
+    comments: Vec<Comment>,
+    language_id: &str,
+    index: &LineIndex,
+    checker: &Checker,
+    state: &State,
+    config: &config::Diagnostics,
+    progress: impl Fn(usize, usize),
+) -> anyhow::Result<Vec<Diagnostic>> {
+    let total = comments.len();
+    futures::stream::iter(comments)
+        .map(|c| diagnose_comment(c, language_id, index, checker, state, config))
         .buffered(10)
-        .try_fold(Vec::new(), |mut b, i| async move {
+        .try_fold((Vec::new(), 0), |(mut b, checked), i| {
             b.extend_from_slice(&i);
-            Ok(b)
+            progress(checked + 1, total);
+            async move { Ok((b, checked + 1)) }
         })
         .await
+        .map(|(diagnostics, _)| diagnostics)
 }
 
 async fn diagnose_comment(
     comment: Comment,
-    document: &str,
-    ltex_client: &languagetool_rust::ServerClient,
+    language_id: &str,
+    index: &LineIndex,
+    checker: &Checker,
     state: &State,
+    config: &config::Diagnostics,
 ) -> anyhow::Result<Vec<Diagnostic>> {
+    let mut matches = Vec::new();
+    for (language, data) in comment.language_annotations(config, &state.language) {
+        let (checked, results) = checker
+            .check(
+                data,
+                language,
+                &config.check,
+                &state.disabled_rules,
+                &state.disabled_categories,
+            )
+            .await?;
+        let language = if language == AUTO_LANGUAGE {
+            checked
+        } else {
+            language.to_owned()
+        };
+        matches.extend(results.into_iter().map(|result| (language.clone(), result)));
+    }
+    let disabled = comment.disabled_regions();
     let mut diagnostics = Vec::new();
-    for result in check_request(ltex_client, comment.tag_markup(), &state.disabled_rules).await {
-        const MISSPELLING: &str = "misspelling";
-        let word = comment
-            .content
-            .get(result.offset..result.offset + result.length)
-            .unwrap_or_else(|| {
-                error!("invalid offset in {result:?}");
-                ""
-            });
+    for (language, result) in matches {
+        let offset = byte_offset(&comment.content, result.offset);
+        let end = offset + byte_offset(&comment.content[offset..], result.length);
+        let word = comment.content.get(offset..end).unwrap_or_else(|| {
+            error!("invalid offset in {result:?}");
+            ""
+        });
 
-        if result.rule.issue_type == MISSPELLING && state.dictionary.contains(word) {
-            debug!("ignoring word in dictionary: `{word}`");
+        let false_positive = FalsePositive {
+            rule: result.rule.id.clone(),
+            text: word.to_owned(),
+            sentence: result.sentence.trim().to_owned(),
+        };
+        if is_ignored(state, &language, &result, &false_positive) {
             continue;
         }
-        // TODO error? because offset is external
-        let start = comment.map_position(document, result.offset);
-        let end = comment.map_position(document, result.offset + result.length);
-
-        // TODO unicode :D
+        if disabled.iter().any(|(range, rules)| {
+            range.contains(&offset) && (rules.is_empty() || rules.contains(&&*result.rule.id))
+        }) {
+            debug!("ignoring match disabled by directive: `{word}`");
+            continue;
+        }
+        let meta = comment_meta(
+            &result,
+            word,
+            language,
+            language_id,
+            comment.kind,
+            false_positive,
+        );
         // TODO code actions
-        diagnostics.push(Diagnostic {
-            range: lsp_types::Range { start, end },
-            severity: Some(DiagnosticSeverity::INFORMATION),
-            code: None,
-            code_description: None,
-            source: Some("ltex".into()),
-            message: result.message,
-            data: Some(
-                serde_json::to_value(Meta {
-                    replacements: result
-                        .replacements
-                        .into_iter()
-                        .take(10)
-                        .map(|r| r.value)
-                        .collect(),
-                    missspelled: (result.rule.issue_type == MISSPELLING).then(|| word.to_owned()),
-                    rule: (result.rule.issue_type != MISSPELLING)
-                        .then_some(result.rule.id),
-                })
-                .unwrap(),
-            ),
-            ..Default::default()
-        });
+        diagnostics.push(match_diagnostic(
+            &result,
+            comment.map_range(index, offset..end),
+            result.message.clone(),
+            meta,
+            config,
+        ));
+    }
+    if let Some(style) = config.list_punctuation {
+        let severity = config
+            .severity
+            .get("typographical", "PUNCTUATION", LIST_PUNCTUATION_RULE)
+            .into();
+        diagnostics.extend(list_punctuation(
+            &comment,
+            language_id,
+            index,
+            style,
+            severity,
+        ));
     }
 
     Ok(diagnostics)
 }
 
-#[cached(
-    size = 500,
-    key = "(Vec<DataAnnotation>, BTreeSet<String>)",
-    convert = "{(data.clone(), disabled_rules.clone())}"
-)]
-async fn check_request(
-    ltex_client: &languagetool_rust::ServerClient,
+/// Whether the match is ignored, as the misspelled word is in the dictionary
+/// or the match was dismissed as false positive.
+fn is_ignored(
+    state: &State,
+    language: &str,
+    result: &Match,
+    false_positive: &FalsePositive,
+) -> bool {
+    let word = &false_positive.text;
+    if result.rule.issue_type == MISSPELLING && state.in_dictionary(language, word) {
+        debug!("ignoring word in dictionary: `{word}`");
+        return true;
+    }
+    if state.false_positives.contains(false_positive) {
+        debug!("ignoring dismissed false positive: `{word}`");
+        return true;
+    }
+    false
+}
+
+/// Diagnostic for the LanguageTool `result` at `range`, with `meta` as data.
+fn match_diagnostic(
+    result: &Match,
+    range: lsp_types::Range,
+    message: String,
+    meta: Meta,
+    config: &config::Diagnostics,
+) -> Diagnostic {
+    let rule = &result.rule;
+    Diagnostic {
+        range,
+        severity: Some(
+            config
+                .severity
+                .get(&rule.issue_type, &rule.category.id, &rule.id)
+                .into(),
+        ),
+        tags: is_unnecessary(rule).then(|| vec![DiagnosticTag::UNNECESSARY]),
+        code: Some(NumberOrString::String(rule.id.clone())),
+        code_description: rule
+            .urls
+            .iter()
+            .flatten()
+            .find_map(|url| Url::parse(&url.value).ok())
+            .map(|href| CodeDescription { href }),
+        source: Some(format!("ltex({})", rule.category.id)),
+        message,
+        data: Some(serde_json::to_value(meta).unwrap()),
+        ..Default::default()
+    }
+}
+
+/// Data of the diagnostic for the LanguageTool `result` matching `word` in a
+/// comment.
+fn comment_meta(
+    result: &Match,
+    word: &str,
+    language: String,
+    language_id: &str,
+    comment_kind: CommentKind,
+    false_positive: FalsePositive,
+) -> Meta {
+    let misspelling = result.rule.issue_type == MISSPELLING;
+    Meta {
+        // the range is clamped to the first line, replacing it would keep the
+        // rest of the match on the following lines
+        replacements: if word.contains('\n') {
+            Vec::new()
+        } else {
+            result
+                .replacements
+                .iter()
+                .take(10)
+                .map(|r| r.value.clone())
+                .collect()
+        },
+        missspelled: misspelling.then(|| word.to_owned()),
+        category: (!misspelling).then(|| Category {
+            id: result.rule.category.id.clone(),
+            name: result.rule.category.name.clone(),
+        }),
+        rule: (!misspelling).then(|| result.rule.id.clone()),
+        language_id: Some(language_id.to_owned()),
+        language: Some(language),
+        comment_kind: Some(comment_kind),
+        false_positive: Some(false_positive),
+        description: Some(result.rule.description.clone()),
+        urls: result
+            .rule
+            .urls
+            .iter()
+            .flatten()
+            .map(|url| url.value.clone())
+            .collect(),
+        identifier: None,
+    }
+}
+
+/// Rules about superfluous whitespace, disabled unless
+/// [`config::Check::whitespace`] is set.
+const WHITESPACE_RULES: [&str; 2] = ["WHITESPACE_RULE", "CONSECUTIVE_SPACES"];
+
+/// Whether the text matched by `rule` can be removed, i.e., whitespace or
+/// redundant phrases, so editors can render it faded.
+fn is_unnecessary(rule: &languagetool_rust::check::Rule) -> bool {
+    rule.issue_type == "whitespace"
+        || rule.category.id == "REDUNDANCY"
+        || WHITESPACE_RULES.contains(&rule.id.as_str())
+}
+
+/// Rule id of [`list_punctuation`] diagnostics, to configure their severity.
+const LIST_PUNCTUATION_RULE: &str = "LIST_PUNCTUATION";
+
+/// Checks whether list items end with a period according to `style`.
+fn list_punctuation(
+    comment: &Comment,
+    language_id: &str,
+    index: &LineIndex,
+    style: config::ListPunctuation,
+    severity: DiagnosticSeverity,
+) -> Vec<Diagnostic> {
+    const TERMINATORS: [char; 5] = ['.', '!', '?', ':', ';'];
+    let mut diagnostics = Vec::new();
+    // range of the last text in each item of the currently open lists
+    let mut lists: Vec<Vec<Option<Range<usize>>>> = Vec::new();
+    for (event, range) in pulldown_cmark::Parser::new(&comment.content).into_offset_iter() {
+        match event {
+            pulldown_cmark::Event::Start(pulldown_cmark::Tag::List(_)) => lists.push(Vec::new()),
+            pulldown_cmark::Event::Start(pulldown_cmark::Tag::Item) => {
+                if let Some(items) = lists.last_mut() {
+                    items.push(None);
+                }
+            }
+            pulldown_cmark::Event::Text(_) | pulldown_cmark::Event::Code(_) => {
+                if let Some(item) = lists.last_mut().and_then(|items| items.last_mut()) {
+                    *item = Some(range);
+                }
+            }
+            pulldown_cmark::Event::End(pulldown_cmark::TagEnd::List(_)) => {
+                let items: Vec<_> = lists
+                    .pop()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .flatten()
+                    .map(|range| {
+                        let text = comment.content[range.clone()].trim_end();
+                        (range.start + text.len(), text)
+                    })
+                    .collect();
+                let period = match style {
+                    config::ListPunctuation::Always => true,
+                    config::ListPunctuation::Never => false,
+                    config::ListPunctuation::Consistent => match items.first() {
+                        Some((_, text)) => text.ends_with('.'),
+                        None => continue,
+                    },
+                };
+                for (end, text) in items {
+                    let Some(last) = text.chars().next_back() else {
+                        continue;
+                    };
+                    let (message, replacement) = if period && !text.ends_with(TERMINATORS) {
+                        ("List item should end with a period.", format!("{last}."))
+                    } else if !period && text.ends_with('.') {
+                        ("List item should not end with a period.", String::new())
+                    } else {
+                        continue;
+                    };
+                    let start = end - last.len_utf8();
+                    diagnostics.push(Diagnostic {
+                        range: comment.map_range(index, start..end),
+                        severity: Some(severity),
+                        code: Some(NumberOrString::String(LIST_PUNCTUATION_RULE.into())),
+                        source: Some("doc-spelling-lsp".into()),
+                        message: message.into(),
+                        data: Some(
+                            serde_json::to_value(Meta {
+                                missspelled: None,
+                                replacements: vec![replacement],
+                                rule: None,
+                                category: None,
+                                language_id: Some(language_id.to_owned()),
+                                language: None,
+                                comment_kind: Some(comment.kind),
+                                false_positive: None,
+                                description: None,
+                                urls: Vec::new(),
+                                identifier: None,
+                            })
+                            .unwrap(),
+                        ),
+                        ..Default::default()
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    diagnostics
+}
+
+/// Language, i.e., the detected one when checking with [`AUTO_LANGUAGE`], and
+/// matches of a check.
+type Checked = (String, Vec<Match>);
+
+/// File the check cache is persisted in, if there is a cache directory.
+pub fn cache_location() -> Option<PathBuf> {
+    directories::BaseDirs::new().map(|base_dirs| {
+        base_dirs
+            .cache_dir()
+            .join("doc-spelling-lsp")
+            .join("checks.json")
+    })
+}
+
+/// LanguageTool client caching the results of previous checks.
+pub struct Checker {
+    client: languagetool_rust::ServerClient,
+    credentials: Option<config::Credentials>,
+    cache: Mutex<SizedCache<CheckKey, Checked>>,
+    /// Minimum interval between requests and when the next one may be sent.
+    throttle: Option<(Duration, tokio::sync::Mutex<Instant>)>,
+    /// Limits the requests in flight across all documents.
+    concurrent_checks: Semaphore,
+    /// When the checks paused by [`RateLimited`] may continue.
+    backoff: Mutex<Instant>,
+    /// Answers checks instead of the server, see [`Checker::mock`].
+    #[cfg(test)]
+    mock: Option<Box<dyn Fn(&str) -> Vec<Match> + Send + Sync>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Hash, PartialEq, Eq)]
+struct CheckKey {
     data: Vec<DataAnnotation>,
-    disabled_rules: &BTreeSet<String>,
-) -> Vec<languagetool_rust::check::Match> {
-    let mut tries = 0;
-    let results = loop {
-        match ltex_client
-            .check(&non_exhaustive!(CheckRequest {
-                data: Some(non_exhaustive!(languagetool_rust::check::Data {
-                    annotation: data.clone()
-                })),
-                language: "en-US".into(),
-                disabled_rules: Some(
-                    disabled_rules
-                        .iter()
-                        .map(ToString::to_string)
-                        .chain(["WHITESPACE_RULE".into(), "CONSECUTIVE_SPACES".into()])
-                        .collect()
-                ),
-                ..CheckRequest::default()
-            }))
-            .await
-        {
-            Ok(results) => break results,
-            Err(e) => {
-                if tries > 10 {
-                    error!("unable to spell check, skipping: {e}");
-                    return Vec::new();
+    language: String,
+    parameters: config::Check,
+    disabled_rules: BTreeSet<String>,
+    disabled_categories: BTreeSet<String>,
+}
+
+impl Checker {
+    pub fn new(
+        client: languagetool_rust::ServerClient,
+        credentials: Option<config::Credentials>,
+        cache_size: usize,
+        max_concurrent_checks: usize,
+        throttle: Option<Duration>,
+    ) -> Self {
+        Self {
+            client,
+            credentials,
+            cache: Mutex::new(SizedCache::with_size(cache_size.max(1))),
+            throttle: throttle.map(|interval| (interval, Instant::now().into())),
+            concurrent_checks: Semaphore::new(max_concurrent_checks.max(1)),
+            backoff: Mutex::new(Instant::now()),
+            #[cfg(test)]
+            mock: None,
+        }
+    }
+
+    /// Checker answering every check with the matches `check` returns for the
+    /// checked text, i.e., text and markup, without a server.
+    #[cfg(test)]
+    pub fn mock(check: impl Fn(&str) -> Vec<Match> + Send + Sync + 'static) -> Self {
+        Self {
+            mock: Some(Box::new(check)),
+            ..Self::new(
+                languagetool_rust::ServerClient::new("http://127.0.0.1", "1"),
+                None,
+                1,
+                1,
+                None,
+            )
+        }
+    }
+
+    /// Adds the results saved by [`Checker::save_cache`] to the cache.
+    pub fn load_cache(&self, file: &Path) {
+        let Ok(content) = fs::read(file) else {
+            return;
+        };
+        match serde_json::from_slice::<Vec<(CheckKey, Checked)>>(&content) {
+            Ok(entries) => {
+                let mut cache = self.cache();
+                for (key, checked) in entries {
+                    cache.cache_set(key, checked);
                 }
-                tries += 1;
-                sleep(Duration::from_secs(1)).await;
             }
+            Err(e) => warn!("unable to load check cache `{}`: {e}", file.display()),
         }
-    };
+    }
+
+    /// Saves the cached results to `file`, to be loaded by
+    /// [`Checker::load_cache`] in the next session.
+    pub fn save_cache(&self, file: &Path) -> anyhow::Result<()> {
+        let content = {
+            let cache = self.cache();
+            // least recently used first, to restore the order when loading
+            let mut entries: Vec<_> = cache.key_order().zip(cache.value_order()).collect();
+            entries.reverse();
+            serde_json::to_vec(&entries).expect("check cache should be serializable")
+        };
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(file, content)
+            .with_context(|| format!("unable to save check cache `{}`", file.display()))
+    }
+
+    /// Number of checks answered from the cache and of those that were not.
+    fn cache_metrics(&self) -> (u64, u64) {
+        let cache = self.cache();
+        (
+            cache.cache_hits().unwrap_or_default(),
+            cache.cache_misses().unwrap_or_default(),
+        )
+    }
+
+    /// Logs the [cache metrics](Checker::cache_metrics) of this session, e.g.,
+    /// on shutdown.
+    pub fn log_cache_metrics(&self) {
+        let (hits, misses) = self.cache_metrics();
+        info!("check cache: {hits} hits, {misses} misses");
+    }
+
+    /// Waits until the throttle allows the next request and a rate limit
+    /// back-off is over.
+    async fn wait_for_throttle(&self) {
+        let backoff = *self
+            .backoff
+            .lock()
+            .expect("backoff lock should not be poisoned");
+        sleep_until(backoff).await;
+        if let Some((interval, next)) = &self.throttle {
+            let mut next = next.lock().await;
+            sleep_until(*next).await;
+            *next = Instant::now() + *interval;
+        }
+    }
+
+    fn cache(&self) -> MutexGuard<'_, SizedCache<CheckKey, Checked>> {
+        self.cache
+            .lock()
+            .expect("cache lock should not be poisoned")
+    }
+
+    /// Waits until the server answers, retrying like every check does, e.g.,
+    /// while a local server is still starting.
+    pub async fn wait_until_ready(&self) -> anyhow::Result<()> {
+        self.check_request(&CheckKey {
+            data: vec![DataAnnotation::new_text("Ready.".into())],
+            language: DEFAULT_LANGUAGE.to_owned(),
+            parameters: config::Check::default(),
+            disabled_rules: BTreeSet::new(),
+            disabled_categories: BTreeSet::new(),
+        })
+        .await
+        .map(drop)
+    }
+
+    async fn check(
+        &self,
+        data: Vec<DataAnnotation>,
+        language: &str,
+        parameters: &config::Check,
+        disabled_rules: &BTreeSet<String>,
+        disabled_categories: &BTreeSet<String>,
+    ) -> anyhow::Result<Checked> {
+        let key = CheckKey {
+            data,
+            language: language.to_owned(),
+            parameters: parameters.clone(),
+            disabled_rules: disabled_rules.clone(),
+            disabled_categories: disabled_categories.clone(),
+        };
+        if let Some(cached) = self.cache().cache_get(&key) {
+            return Ok(cached.clone());
+        }
+        let matches = self.check_request(&key).await?;
+        self.cache().cache_set(key, matches.clone());
+        Ok(matches)
+    }
+
+    async fn check_request(
+        &self,
+        CheckKey {
+            data,
+            language,
+            parameters,
+            disabled_rules,
+            disabled_categories,
+        }: &CheckKey,
+    ) -> anyhow::Result<Checked> {
+        #[cfg(test)]
+        if let Some(check) = &self.mock {
+            let text: String = data
+                .iter()
+                .filter_map(|annotation| {
+                    annotation.text.as_deref().or(annotation.markup.as_deref())
+                })
+                .collect();
+            return Ok((language.clone(), check(&text)));
+        }
+        if log_enabled!(target: PAYLOAD_LOG_TARGET, Level::Trace) {
+            trace!(
+                target: PAYLOAD_LOG_TARGET,
+                "request annotations: {}",
+                serde_json::to_string(data).expect("annotations can be serialized")
+            );
+        }
+        let disabled_categories: Vec<_> = disabled_categories
+            .iter()
+            .chain(&parameters.disabled_categories)
+            .cloned()
+            .collect();
+        let disabled_categories = (!disabled_categories.is_empty()).then_some(disabled_categories);
+        let mut tries = 0;
+        let results = loop {
+            let permit = self
+                .concurrent_checks
+                .acquire()
+                .await
+                .expect("semaphore is never closed");
+            self.wait_for_throttle().await;
+            match self
+                .client
+                .check(&non_exhaustive!(CheckRequest {
+                    data: Some(non_exhaustive!(languagetool_rust::check::Data {
+                        annotation: data.clone()
+                    })),
+                    language: language.clone(),
+                    mother_tongue: parameters
+                        .mother_tongue
+                        .clone()
+                        .filter(|mother_tongue| !mother_tongue.is_empty()),
+                    level: match parameters.level {
+                        config::Level::Default => languagetool_rust::check::Level::Default,
+                        config::Level::Picky => languagetool_rust::check::Level::Picky,
+                    },
+                    preferred_variants: (!parameters.preferred_variants.is_empty())
+                        .then(|| parameters.preferred_variants.clone()),
+                    username: self.credentials.as_ref().map(|c| c.username.clone()),
+                    api_key: self.credentials.as_ref().map(|c| c.api_key.clone()),
+                    disabled_rules: Some(
+                        disabled_rules
+                            .iter()
+                            .map(ToString::to_string)
+                            .chain(
+                                WHITESPACE_RULES
+                                    .into_iter()
+                                    .filter(|_| !parameters.whitespace)
+                                    .map(ToOwned::to_owned)
+                            )
+                            .collect()
+                    ),
+                    disabled_categories: disabled_categories.clone(),
+                    enabled_rules: (!parameters.enabled_rules.is_empty())
+                        .then(|| parameters.enabled_rules.clone()),
+                    enabled_categories: (!parameters.enabled_categories.is_empty())
+                        .then(|| parameters.enabled_categories.clone()),
+                    ..CheckRequest::default()
+                }))
+                .await
+            {
+                Ok(results) => break results,
+                // retrying would only extend the limit
+                Err(languagetool_rust::error::Error::InvalidRequest(message))
+                    if is_rate_limit(&message) =>
+                {
+                    let until = Instant::now() + RATE_LIMIT_WINDOW;
+                    *self
+                        .backoff
+                        .lock()
+                        .expect("backoff lock should not be poisoned") = until;
+                    return Err(RateLimited { until, message }.into());
+                }
+                Err(e) => {
+                    drop(permit);
+                    if tries > 10 {
+                        bail!("unable to reach LanguageTool server: {e}");
+                    }
+                    tries += 1;
+                    sleep(Duration::from_secs(1)).await;
+                }
+            }
+        };
+        if log_enabled!(target: PAYLOAD_LOG_TARGET, Level::Trace) {
+            trace!(
+                target: PAYLOAD_LOG_TARGET,
+                "response matches: {}",
+                serde_json::to_string(&results.matches).expect("matches can be serialized")
+            );
+        }
+
+        // the detected language if checked with `auto`
+        Ok((results.language.code, results.matches))
+    }
+}
+
+/// LanguageTool rejected a check for exceeding the rate limit or quota, the
+/// following checks wait until `until` instead.
+#[derive(thiserror::Error, Debug)]
+#[error("LanguageTool rate limit or quota exceeded, checks are paused for a minute: {message}")]
+pub struct RateLimited {
+    pub until: Instant,
+    message: String,
+}
+
+/// Whether the error `message` of the server is about the rate limit, i.e.,
+/// `429 Too Many Requests`, or the quota.
+fn is_rate_limit(message: &str) -> bool {
+    let message = message.to_lowercase();
+    ["too many requests", "rate limit", "quota"]
+        .iter()
+        .any(|reason| message.contains(reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn position(line: u32, character: u32) -> Position {
+        Position { line, character }
+    }
+
+    /// LanguageTool match of `rule` with `issue_type`, replacing `length` bytes
+    /// at `offset` with `replacement`.
+    fn languagetool_match(
+        rule: &str,
+        issue_type: &str,
+        (offset, length): (usize, usize),
+        replacement: &str,
+    ) -> Match {
+        serde_json::from_value(serde_json::json!({
+            "message": "Possible mistake found.",
+            "shortMessage": "",
+            "replacements": [{ "value": replacement }],
+            "offset": offset,
+            "length": length,
+            "context": { "text": "", "offset": 0, "length": 0 },
+            "sentence": "",
+            "type": { "typeName": "Other" },
+            "rule": {
+                "id": rule,
+                "description": "Possible mistake",
+                "issueType": issue_type,
+                "category": { "id": "CATEGORY", "name": "Category" },
+                "urls": [{ "value": "https://example.com/rule" }],
+            },
+            "ignoreForIncompleteSentence": false,
+            "contextForSureMatch": 0,
+        }))
+        .expect("match is valid")
+    }
+
+    /// Text LanguageTool checks, i.e., the text and the interpretation of the
+    /// markup.
+    fn interpreted(annotations: &[DataAnnotation]) -> String {
+        annotations
+            .iter()
+            .filter_map(|annotation| {
+                annotation
+                    .text
+                    .as_deref()
+                    .or(annotation.interpret_as.as_deref())
+            })
+            .collect()
+    }
+
+    /// Text and markup sent to LanguageTool, which its offsets refer to.
+    fn checked(annotations: &[DataAnnotation]) -> String {
+        annotations
+            .iter()
+            .filter_map(|annotation| annotation.text.as_deref().or(annotation.markup.as_deref()))
+            .collect()
+    }
+
+    /// Annotated as text, i.e., checked as written.
+    fn texts(annotations: &[DataAnnotation]) -> String {
+        annotations
+            .iter()
+            .filter_map(|annotation| annotation.text.as_deref())
+            .collect()
+    }
+
+    fn only_comment(document: &str) -> Comment {
+        let mut comments = doc_comments(document);
+        assert_eq!(comments.len(), 1, "expected one comment in {document:?}");
+        comments.remove(0)
+    }
+
+    #[test]
+    fn apply_change_uses_encoding() {
+        let mut document = "ä🦀 wrld\nnext".to_owned();
+        apply_change(
+            &mut document,
+            &PositionEncodingKind::UTF16,
+            lsp_types::Range {
+                start: position(0, 4),
+                end: position(0, 8),
+            },
+            "world",
+        );
+        assert_eq!(document, "ä🦀 world\nnext");
+        apply_change(
+            &mut document,
+            &PositionEncodingKind::UTF16,
+            lsp_types::Range {
+                start: position(0, 9),
+                end: position(1, 0),
+            },
+            " ",
+        );
+        assert_eq!(document, "ä🦀 world next");
+    }
+
+    #[test]
+    fn doc_comments_group_consecutive_lines() {
+        let document = "//! Crate docs.\n\n/// First\n///second\nfn f() {}\n// not docs\n/// Other";
+        let comments: Vec<_> = doc_comments(document)
+            .into_iter()
+            .map(|comment| (comment.kind, comment.content))
+            .collect();
+        assert_eq!(comments, [
+            (CommentKind::Inner, "Crate docs.\n".to_owned()),
+            (CommentKind::Outer, "First\nsecond\n".to_owned()),
+            (CommentKind::Outer, "Other\n".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn doc_comments_strip_block_comments() {
+        let document = concat!(
+            "/// Line\n/**\n * First\n *\n *  indented\n */\n",
+            "fn f() {}\n/*! Inner\n    docs */\n/** Single */",
+        );
+        let comments: Vec<_> = doc_comments(document)
+            .into_iter()
+            .map(|comment| (comment.kind, comment.content))
+            .collect();
+        assert_eq!(comments, [
+            (CommentKind::Outer, "Line\n".to_owned()),
+            (CommentKind::Outer, "First\n\n indented\n".to_owned()),
+            (CommentKind::Inner, "Inner\ndocs\n".to_owned()),
+            (CommentKind::Outer, "Single\n".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn doc_comments_accept_unterminated_block_comments() {
+        assert_eq!(
+            only_comment("/** Unterminated\n * text").content,
+            "Unterminated\ntext\n"
+        );
+        assert_eq!(only_comment("/** */").content, "\n");
+    }
+
+    #[test]
+    fn doc_comments_parse_other_languages() {
+        let javascript = concat!(
+            "/**\n * Adds two numbers.\n *\n * @param {number} a\n */\n",
+            "function add(a, b) { return 'a' + `b`; }",
+        );
+        assert_eq!(
+            only_comment(javascript).content,
+            "Adds two numbers.\n\n@param {number} a\n"
+        );
+        let java = "/** Returns the sum. */\npublic int add(int a, int b) { return a + b; }";
+        assert_eq!(only_comment(java).content, "Returns the sum.\n");
+        let c = "/**\n   Without gutter,\n     indented.\n*/\nint add(int a, int b);";
+        assert_eq!(only_comment(c).content, "Without gutter,\n  indented.\n");
+    }
+
+    #[test]
+    fn block_comment_maps_to_document() {
+        let document = "/** Some\n * wrld */";
+        let comment = only_comment(document);
+        let index = LineIndex::new(document, &PositionEncodingKind::UTF16);
+        let wrld = comment.content.find("wrld").unwrap();
+        assert_eq!(
+            comment.map_range(&index, wrld..wrld + 4),
+            lsp_types::Range {
+                start: position(1, 3),
+                end: position(1, 7),
+            }
+        );
+    }
+
+    #[test]
+    fn map_range_maps_each_line() {
+        let document = "fn f() {\n    /// äb\n    /// cd\n}";
+        let comment = only_comment(document);
+        let index = LineIndex::new(document, &PositionEncodingKind::UTF16);
+        assert_eq!(
+            comment.map_range(&index, 0.."äb".len()),
+            lsp_types::Range {
+                start: position(1, 8),
+                end: position(1, 10),
+            }
+        );
+        let cd = comment.content.find("cd").unwrap();
+        assert_eq!(comment.map_range(&index, cd..cd + 2), lsp_types::Range {
+            start: position(2, 8),
+            end: position(2, 10),
+        });
+    }
+
+    #[test]
+    fn map_range_counts_code_units_of_the_encoding() {
+        let document = "/// 🦀 ä wrld";
+        let comment = only_comment(document);
+        let wrld = comment.content.find("wrld").unwrap();
+        for (encoding, start) in [
+            (PositionEncodingKind::UTF8, 12),
+            (PositionEncodingKind::UTF16, 9),
+            (PositionEncodingKind::UTF32, 8),
+        ] {
+            let index = LineIndex::new(document, &encoding);
+            assert_eq!(
+                comment.map_range(&index, wrld..wrld + 4),
+                lsp_types::Range {
+                    start: position(0, start),
+                    end: position(0, start + 4),
+                },
+                "{encoding:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn map_range_clamps_to_the_line_of_its_start() {
+        let document = "/// ab\n/// cd";
+        let comment = only_comment(document);
+        let index = LineIndex::new(document, &PositionEncodingKind::UTF16);
+        let first_line = lsp_types::Range {
+            start: position(0, 4),
+            end: position(0, 6),
+        };
+        // spanning the join of both lines
+        assert_eq!(comment.map_range(&index, 0..5), first_line);
+        // including the appended `\n`
+        assert_eq!(comment.map_range(&index, 0..3), first_line);
+        assert_eq!(
+            comment.map_range(&index, 3..comment.content.len()),
+            lsp_types::Range {
+                start: position(1, 4),
+                end: position(1, 6),
+            }
+        );
+        // starting at the join
+        assert_eq!(comment.map_range(&index, 2..4), lsp_types::Range {
+            start: position(0, 6),
+            end: position(0, 6),
+        });
+    }
+
+    #[test]
+    fn tag_markup_interprets_inline_markup() {
+        let comment = only_comment("/// Some `code` and *emphasis*.");
+        let annotations = comment.tag_markup(&config::Diagnostics::default());
+        assert_eq!(interpreted(&annotations), "Some 0 and emphasis.\n");
+    }
+
+    #[test]
+    fn tag_markup_skips_code_blocks() {
+        let comment = only_comment("/// Text\n///\n/// ```\n/// let x = wrld;\n/// ```");
+        let annotations = comment.tag_markup(&config::Diagnostics::default());
+        assert!(texts(&annotations).contains("Text"));
+        assert!(!interpreted(&annotations).contains("wrld"));
+    }
+
+    #[test]
+    fn tag_markup_prefixes_headings() {
+        let comment = only_comment("/// # Title");
+        let annotations = comment.tag_markup(&config::Diagnostics::default());
+        assert!(interpreted(&annotations).starts_with("Heading: Title"));
+    }
+
+    #[test]
+    fn tag_markup_checks_link_text_but_not_paths() {
+        let comment = only_comment(
+            "/// See [the docs](crate::foo::Foo), [crate::bar_baz], [`Baz`] and [Chekced].",
+        );
+        let annotations = comment.tag_markup(&config::Diagnostics::default());
+        assert_eq!(texts(&annotations), "See the docs, ,  and Chekced.");
+        assert!(!interpreted(&annotations).contains("crate"));
+    }
 
-    results.matches
+    #[test]
+    fn tag_markup_skips_urls() {
+        let comment = only_comment(concat!(
+            "/// See <https://exmple.com/wrld>, <mail@exmple.com>, ",
+            "[the sitee](https://exmple.com/wrld \"Titel\") and ![an imge](wrld.png).",
+        ));
+        let annotations = comment.tag_markup(&config::Diagnostics::default());
+        let texts = texts(&annotations);
+        assert!(texts.contains("the sitee") && texts.contains("an imge"));
+        assert!(!texts.contains("exmple") && !texts.contains("wrld") && !texts.contains("Titel"));
+    }
+
+    #[test]
+    fn tag_markup_skips_inline_html_code() {
+        let comment = only_comment(
+            "/// Call <code>wrld</code> or press <kbd>Ctrl</kbd> to greet the <b>wrld</b>.",
+        );
+        let annotations = comment.tag_markup(&config::Diagnostics::default());
+        assert_eq!(texts(&annotations), "Call  or press  to greet the wrld.");
+        assert_eq!(
+            interpreted(&annotations),
+            "Call 0 or press 0 to greet the wrld.\n"
+        );
+    }
+
+    #[test]
+    fn tag_markup_checks_text_of_html_blocks() {
+        let comment = only_comment("/// <div>\n/// Some <code>wrld</code> &amp; more.\n/// </div>");
+        let annotations = comment.tag_markup(&config::Diagnostics::default());
+        assert!(interpreted(&annotations).contains("\nSome 0 & more.\n"));
+        assert!(!texts(&annotations).contains("wrld"));
+    }
+
+    #[test]
+    fn tag_markup_skips_script_and_style() {
+        let comment = only_comment(concat!(
+            "/// <script>\n/// let wrld = 1;\n/// </script>\n///\n",
+            "/// <style>\n/// .wrld {}\n/// </style>\n///\n",
+            "/// Checked again.",
+        ));
+        let annotations = comment.tag_markup(&config::Diagnostics::default());
+        let interpreted = interpreted(&annotations);
+        assert!(!interpreted.contains("wrld"));
+        assert!(interpreted.contains("Checked again."));
+    }
+
+    #[test]
+    fn html_entity_decodes_named_and_numeric_entities() {
+        assert_eq!(html_entity("&amp; rest"), Some((5, '&')));
+        assert_eq!(html_entity("&nbsp;"), Some((6, ' ')));
+        assert_eq!(html_entity("&#x2014;"), Some((8, '—')));
+        assert_eq!(html_entity("&#8212;"), Some((7, '—')));
+        assert_eq!(html_entity("&unknown;"), None);
+        assert_eq!(html_entity("& no entity;"), None);
+        assert_eq!(html_entity("&#xD800;"), None);
+    }
+
+    #[test]
+    fn html_annotations_keep_offsets_of_entities() {
+        let html = "Fish &amp; chips &#x1F980; &wrld";
+        let annotations = html_annotations(html, &mut 0, &mut 0);
+        assert_eq!(interpreted(&annotations), "Fish & chips 🦀 &wrld");
+        // the checked text covers the html, so match offsets map back into it
+        assert_eq!(checked(&annotations), html);
+        let entity = annotations
+            .iter()
+            .find(|annotation| annotation.interpret_as.as_deref() == Some("🦀"))
+            .unwrap();
+        assert_eq!(entity.markup.as_deref(), Some("&#x1F980;"));
+    }
+
+    #[test]
+    fn language_annotations_split_at_language_directives() {
+        let comment = only_comment(concat!(
+            "/// Some text.\n",
+            "/// <!-- doc-spelling: language=de-DE -->\n",
+            "/// Ein Satz.\n",
+            "/// <!-- doc-spelling: language=en-US -->\n",
+            "/// More text.",
+        ));
+        let content = &comment.content;
+        let german = content.find("<!-- doc-spelling: language=de-DE").unwrap();
+        let english = content.find("<!-- doc-spelling: language=en-US").unwrap();
+        assert_eq!(comment.language_regions("auto"), [
+            ("auto", 0..german),
+            ("de-DE", german..english),
+            ("en-US", english..content.len()),
+        ]);
+
+        let annotations = comment.language_annotations(&config::Diagnostics::default(), "auto");
+        let texts: Vec<_> = annotations
+            .iter()
+            .map(|(language, annotations)| (*language, texts(annotations)))
+            .collect();
+        let texts: Vec<_> = texts
+            .iter()
+            .map(|(language, text)| (*language, text.trim()))
+            .collect();
+        assert_eq!(texts, [
+            ("auto", "Some text."),
+            ("de-DE", "Ein Satz."),
+            ("en-US", "More text.")
+        ]);
+        // every language checks the whole comment, so offsets are shared
+        for annotations in annotations.values() {
+            assert_eq!(checked(annotations), *content);
+        }
+    }
+
+    /// `document` with the fixes of its list punctuation diagnostics applied.
+    fn fix_list_punctuation(document: &str, style: config::ListPunctuation) -> String {
+        let encoding = PositionEncodingKind::UTF8;
+        let index = LineIndex::new(document, &encoding);
+        let diagnostics = list_punctuation(
+            &only_comment(document),
+            "rust",
+            &index,
+            style,
+            DiagnosticSeverity::HINT,
+        );
+        let mut fixed = document.to_owned();
+        // last first, so the ranges of the remaining fixes stay valid
+        for diagnostic in diagnostics.into_iter().rev() {
+            let meta: Meta = serde_json::from_value(diagnostic.data.unwrap()).unwrap();
+            apply_change(
+                &mut fixed,
+                &encoding,
+                diagnostic.range,
+                &meta.replacements[0],
+            );
+        }
+        fixed
+    }
+
+    #[test]
+    fn list_punctuation_fixes_items_according_to_style() {
+        let document = "/// - First item.\n/// - Second item\n/// - Third item:";
+        assert_eq!(
+            fix_list_punctuation(document, config::ListPunctuation::Always),
+            "/// - First item.\n/// - Second item.\n/// - Third item:"
+        );
+        assert_eq!(
+            fix_list_punctuation(document, config::ListPunctuation::Never),
+            "/// - First item\n/// - Second item\n/// - Third item:"
+        );
+        assert_eq!(
+            fix_list_punctuation(document, config::ListPunctuation::Consistent),
+            "/// - First item.\n/// - Second item.\n/// - Third item:"
+        );
+        assert_eq!(
+            fix_list_punctuation(
+                "/// - First\n/// - Second.",
+                config::ListPunctuation::Consistent
+            ),
+            "/// - First\n/// - Second"
+        );
+    }
+
+    #[test]
+    fn is_rust_path_detects_paths() {
+        for path in ["crate::Foo", "HashMap", "foo_bar"] {
+            assert!(is_rust_path(path), "{path}");
+        }
+        for prose in ["docs", "Foo", "the docs", ""] {
+            assert!(!is_rust_path(prose), "{prose}");
+        }
+    }
+
+    #[test]
+    fn comment_meta_separates_misspellings_from_rules() {
+        let false_positive = FalsePositive {
+            rule: "RULE".to_owned(),
+            text: "wrld".to_owned(),
+            sentence: "Hello wrld".to_owned(),
+        };
+        let misspelling = comment_meta(
+            &languagetool_match("RULE", MISSPELLING, (6, 4), "world"),
+            "wrld",
+            "en-US".to_owned(),
+            "rust",
+            CommentKind::Outer,
+            false_positive.clone(),
+        );
+        assert_eq!(misspelling.missspelled.as_deref(), Some("wrld"));
+        assert_eq!(misspelling.replacements, ["world"]);
+        assert!(misspelling.rule.is_none() && misspelling.category.is_none());
+        assert_eq!(misspelling.urls, ["https://example.com/rule"]);
+
+        let grammar = comment_meta(
+            &languagetool_match("RULE", "grammar", (6, 6), "world"),
+            "wrld\nx",
+            "en-US".to_owned(),
+            "rust",
+            CommentKind::Outer,
+            false_positive,
+        );
+        assert!(grammar.missspelled.is_none());
+        assert_eq!(grammar.rule.as_deref(), Some("RULE"));
+        assert_eq!(grammar.category.unwrap().id, "CATEGORY");
+        // replacing the first line only would keep the rest
+        assert!(grammar.replacements.is_empty());
+    }
+
+    #[test]
+    fn match_diagnostic_uses_rule_and_config() {
+        let mut config = config::Diagnostics::default();
+        config
+            .severity
+            .rules
+            .insert("RULE".to_owned(), config::Severity::Error);
+        let result = languagetool_match("RULE", "whitespace", (0, 1), "");
+        let meta = comment_meta(
+            &result,
+            " ",
+            "en-US".to_owned(),
+            "rust",
+            CommentKind::Outer,
+            FalsePositive {
+                rule: "RULE".to_owned(),
+                text: " ".to_owned(),
+                sentence: String::new(),
+            },
+        );
+        let range = lsp_types::Range::new(position(0, 4), position(0, 5));
+        let diagnostic = match_diagnostic(&result, range, result.message.clone(), meta, &config);
+        assert_eq!(diagnostic.range, range);
+        assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(diagnostic.tags, Some(vec![DiagnosticTag::UNNECESSARY]));
+        assert_eq!(
+            diagnostic.code,
+            Some(NumberOrString::String("RULE".to_owned()))
+        );
+        assert_eq!(
+            diagnostic.code_description.unwrap().href.as_str(),
+            "https://example.com/rule"
+        );
+        assert_eq!(diagnostic.source.as_deref(), Some("ltex(CATEGORY)"));
+        let meta: Meta = serde_json::from_value(diagnostic.data.unwrap()).unwrap();
+        assert_eq!(meta.rule.as_deref(), Some("RULE"));
+    }
+
+    #[test]
+    fn nesting_tracks_code_blocks_and_links() {
+        use pulldown_cmark::{Event, Tag, TagEnd};
+
+        let config = config::Diagnostics::default();
+        let mut nesting = Nesting::default();
+        let text = |nesting: &mut Nesting| {
+            nesting.annotation(Event::Text("x".into()), "x".into(), &config)
+        };
+        let fence = Tag::CodeBlock(CodeBlockKind::Fenced("rust".into()));
+        nesting.annotation(Event::Start(fence), "```rust\n".into(), &config);
+        assert!(nesting.code_block == 1 && nesting.rust_code_block);
+        assert_eq!(text(&mut nesting), DataAnnotation::new_markup("x".into()));
+        nesting.annotation(Event::End(TagEnd::CodeBlock), "```".into(), &config);
+        assert_eq!(nesting.code_block, 0);
+        assert_eq!(text(&mut nesting), DataAnnotation::new_text("x".into()));
+
+        let link = Tag::Link {
+            link_type: LinkType::Autolink,
+            dest_url: "https://example.com".into(),
+            title: "".into(),
+            id: "".into(),
+        };
+        nesting.annotation(Event::Start(link), "<".into(), &config);
+        assert_eq!(
+            text(&mut nesting),
+            DataAnnotation::new_interpreted_markup("x".into(), "0".into())
+        );
+        nesting.annotation(Event::End(TagEnd::Link), ">".into(), &config);
+        assert!(nesting.link == 0 && !nesting.autolink);
+    }
+
+    #[test]
+    fn identifier_words_by_text_groups_occurrences() {
+        let document = "fn parse_wrld(wrld_map: u8) -> ID { let wrld = parse_wrld(0); }";
+        let words = identifier_words_by_text(document);
+        assert_eq!(words.keys().copied().collect::<Vec<_>>(), [
+            "map", "parse", "wrld"
+        ]);
+        let function = document.find("parse_wrld").unwrap();
+        let map = document.find("wrld_map").unwrap();
+        let binding = document.find("wrld =").unwrap();
+        // the call is no definition
+        assert_eq!(words["wrld"], [
+            (function..function + 10, function + 6..function + 10),
+            (map..map + 8, map..map + 4),
+            (binding..binding + 4, binding..binding + 4),
+        ]);
+    }
+
+    #[test]
+    fn definitions_skip_keywords_and_external_names() {
+        let document = "use std::fmt;\n\
+                        /// A colr.\n\
+                        pub struct Colr<Chanel: fmt::Debug> {\n    \
+                            #[serde(default)]\n    \
+                            pub(crate) chanels: Vec<Chanel>,\n    \
+                            alpa: u8,\n\
+                        }\n\
+                        async fn mix(self, mut othr: Self) -> Colr<u8> {\n    \
+                            let mut blnd = Colr { chanels: args(), alpa: othr.alpa };\n\
+                        }";
+        let names: Vec<_> = definitions(document)
+            .into_iter()
+            .map(|range| &document[range])
+            .collect();
+        assert_eq!(names, [
+            "Colr", "Chanel", "chanels", "alpa", "mix", "othr", "blnd", "chanels", "alpa"
+        ]);
+    }
+
+    #[test]
+    fn is_ignored_checks_dictionary_and_false_positives() {
+        let occurrence = |text: &str| FalsePositive {
+            rule: "RULE".to_owned(),
+            text: text.to_owned(),
+            sentence: "Some sentence.".to_owned(),
+        };
+        let typo = languagetool_match("RULE", MISSPELLING, (0, 4), "world");
+        let grammar = languagetool_match("RULE", "grammar", (0, 4), "world");
+        let mut state = State::default();
+        state.external_dictionary.insert("wrld".to_owned());
+        state.false_positives.insert(occurrence("teh"));
+        assert!(is_ignored(&state, "en-US", &typo, &occurrence("wrld")));
+        // the dictionary only contains correctly spelled words
+        assert!(!is_ignored(&state, "en-US", &grammar, &occurrence("wrld")));
+        assert!(is_ignored(&state, "en-US", &grammar, &occurrence("teh")));
+        assert!(!is_ignored(&state, "en-US", &typo, &occurrence("other")));
+    }
+
+    #[tokio::test]
+    async fn rate_limit_pauses_checks_without_retrying() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(AtomicUsize::new(0));
+        {
+            let requests = requests.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    // the parameters are in the query, so there is no body
+                    let mut line = String::new();
+                    let mut reader = BufReader::new(&stream);
+                    while reader.read_line(&mut line).unwrap() > "\r\n".len() {
+                        line.clear();
+                    }
+                    let body = "Too many requests, please try again later.";
+                    write!(
+                        stream,
+                        "HTTP/1.1 429 Too Many Requests\r\nContent-Length: {}\r\n\
+                         Connection: close\r\n\r\n{body}",
+                        body.len()
+                    )
+                    .unwrap();
+                }
+            });
+        }
+        let checker = Checker::new(
+            languagetool_rust::ServerClient::new("http://127.0.0.1", &port.to_string()),
+            None,
+            1,
+            1,
+            None,
+        );
+        let (parameters, disabled) = (config::Check::default(), BTreeSet::new());
+        let check = || {
+            checker.check(
+                vec![DataAnnotation::new_text("Hello.".into())],
+                DEFAULT_LANGUAGE,
+                &parameters,
+                &disabled,
+                &disabled,
+            )
+        };
+        let error = check().await.unwrap_err();
+        let limited = error
+            .downcast_ref::<RateLimited>()
+            .expect("rate limit should be detected");
+        assert!(limited.until > Instant::now() + RATE_LIMIT_WINDOW / 2);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        // the next check waits for the back-off instead of sending a request
+        let waiting = tokio::time::timeout(Duration::from_millis(200), check()).await;
+        assert!(waiting.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn cache_metrics_count_repeated_checks() {
+        let checker = Checker::mock(|_| Vec::new());
+        let (parameters, disabled) = (config::Check::default(), BTreeSet::new());
+        for text in ["Hello.", "Hello.", "World."] {
+            checker
+                .check(
+                    vec![DataAnnotation::new_text(text.into())],
+                    DEFAULT_LANGUAGE,
+                    &parameters,
+                    &disabled,
+                    &disabled,
+                )
+                .await
+                .unwrap();
+        }
+        assert_eq!(checker.cache_metrics(), (1, 2));
+    }
 }
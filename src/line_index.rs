@@ -0,0 +1,111 @@
+use lsp_types::{Position, PositionEncodingKind};
+
+/// Maps byte offsets in a document to [`Position`]s.
+pub struct LineIndex<'a> {
+    document: &'a str,
+    encoding: &'a PositionEncodingKind,
+    /// Byte offset of the start of each line.
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(document: &'a str, encoding: &'a PositionEncodingKind) -> Self {
+        // splitting at `\n` also handles `\r\n`
+        let line_starts = [0]
+            .into_iter()
+            .chain(document.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        Self {
+            document,
+            encoding,
+            line_starts,
+        }
+    }
+
+    /// Byte offset of `position`, clamped to the end of its line and the end
+    /// of the document.
+    pub fn offset(&self, position: Position) -> usize {
+        let Some(&line_start) = self.line_starts.get(position.line as usize) else {
+            return self.document.len();
+        };
+        let line = &self.document[line_start..];
+        let line = &line[..line.find('\n').unwrap_or(line.len())];
+        let mut character = 0;
+        for (offset, c) in line.char_indices() {
+            if character >= position.character as usize {
+                return line_start + offset;
+            }
+            character += encoded_len(c.encode_utf8(&mut [0; 4]), self.encoding);
+        }
+        line_start + line.len()
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let line_prefix = &self.document[self.line_starts[line]..offset];
+        Position {
+            line: line.try_into().unwrap(),
+            character: encoded_len(line_prefix, self.encoding).try_into().unwrap(),
+        }
+    }
+}
+
+/// Length of `text` in the code units of `encoding`, defaulting to UTF-16 as
+/// the LSP specification requires.
+pub fn encoded_len(text: &str, encoding: &PositionEncodingKind) -> usize {
+    if *encoding == PositionEncodingKind::UTF8 {
+        text.len()
+    } else if *encoding == PositionEncodingKind::UTF32 {
+        text.chars().count()
+    } else {
+        text.encode_utf16().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(line: u32, character: u32) -> Position {
+        Position { line, character }
+    }
+
+    #[test]
+    fn encoded_len_counts_code_units() {
+        // `ä` is one UTF-16 code unit, `🦀` a surrogate pair
+        let text = "aä🦀";
+        assert_eq!(encoded_len(text, &PositionEncodingKind::UTF8), 7);
+        assert_eq!(encoded_len(text, &PositionEncodingKind::UTF16), 4);
+        assert_eq!(encoded_len(text, &PositionEncodingKind::UTF32), 3);
+    }
+
+    #[test]
+    fn line_index_maps_in_each_encoding() {
+        let document = "ä🦀x\nsecond\r\nthird";
+        let x = "ä🦀".len();
+        let third = document.find("third").unwrap();
+        for (encoding, character) in [
+            (PositionEncodingKind::UTF8, 6),
+            (PositionEncodingKind::UTF16, 3),
+            (PositionEncodingKind::UTF32, 2),
+        ] {
+            let index = LineIndex::new(document, &encoding);
+            assert_eq!(index.position(x), position(0, character));
+            assert_eq!(index.offset(position(0, character)), x);
+            assert_eq!(index.position(third), position(2, 0));
+            assert_eq!(index.offset(position(2, 2)), third + 2);
+        }
+    }
+
+    #[test]
+    fn line_index_clamps_positions() {
+        let document = "ab\ncd";
+        let index = LineIndex::new(document, &PositionEncodingKind::UTF16);
+        assert_eq!(index.offset(position(0, 10)), 2);
+        assert_eq!(index.offset(position(5, 0)), document.len());
+        assert_eq!(index.position(document.len()), position(1, 2));
+        // inside the surrogate pair
+        let index = LineIndex::new("🦀a", &PositionEncodingKind::UTF16);
+        assert_eq!(index.offset(position(0, 1)), "🦀".len());
+    }
+}
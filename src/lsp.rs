@@ -1,8 +1,11 @@
 #![allow(unused)]
 use std::collections::{HashMap, HashSet};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicI32, Ordering};
 use std::sync::Arc;
 use std::task::Poll;
 use std::thread;
+use std::time::Duration;
 
 // TODO remove anyhow from a lib maybe :D
 use anyhow::{bail, Context as _};
@@ -14,14 +17,25 @@ use futures::future::BoxFuture;
 use futures::{stream, FutureExt, SinkExt, StreamExt};
 use log::{error, info, warn};
 use lsp_server::{Connection, IoThreads, Message, RequestId, Response, ResponseError};
-use lsp_types::notification::{DidChangeTextDocument, Notification, PublishDiagnostics};
-use lsp_types::request::Request;
-use lsp_types::{Diagnostic, InitializeParams, PublishDiagnosticsParams, ServerCapabilities, Url};
+use lsp_types::notification::{
+    Cancel, DidChangeTextDocument, Exit, LogMessage, Notification, Progress, PublishDiagnostics,
+    ShowMessage,
+};
+use lsp_types::request::{ApplyWorkspaceEdit, Request, ShowMessageRequest, WorkDoneProgressCreate};
+use lsp_types::{
+    ApplyWorkspaceEditParams, ApplyWorkspaceEditResponse, CancelParams, Diagnostic,
+    InitializeParams, InitializeResult, LogMessageParams, MessageActionItem, MessageType,
+    NumberOrString, PositionEncodingKind, ProgressParams, ProgressParamsValue, ProgressToken,
+    PublishDiagnosticsParams, ServerCapabilities, ShowMessageParams, ShowMessageRequestParams, Url,
+    WorkDoneProgress, WorkDoneProgressBegin, WorkDoneProgressCreateParams, WorkDoneProgressEnd,
+    WorkspaceEdit,
+};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use serde_json::Value;
 use tokio::sync::mpsc::unbounded_channel;
-use tokio::task::{JoinHandle, JoinSet};
+use tokio::sync::oneshot;
+use tokio::task::{AbortHandle, JoinHandle, JoinSet};
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -33,9 +47,14 @@ fn from_value<T: DeserializeOwned>(value: Value) -> Result<T> {
     Ok(serde_json::from_value(value)?)
 }
 
+/// Time the client has to create a progress before it is not reported, so
+/// callers are not blocked by clients that never respond.
+const PROGRESS_CREATE_TIMEOUT: Duration = Duration::from_secs(1);
+
 pub struct Builder<Options = ()> {
     connection: Connection,
-    threads: IoThreads,
+    /// `None` for connections without io threads, e.g., in memory.
+    threads: Option<IoThreads>,
     server_capabilities: ServerCapabilities,
     options: Options,
 }
@@ -46,7 +65,18 @@ impl Builder {
 
         Self {
             connection,
-            threads,
+            threads: Some(threads),
+            server_capabilities: ServerCapabilities::default(),
+            options: (),
+        }
+    }
+
+    /// Serves `connection`, e.g., one end of [`Connection::memory`].
+    #[cfg(test)]
+    pub fn connection(connection: Connection) -> Self {
+        Self {
+            connection,
+            threads: None,
             server_capabilities: ServerCapabilities::default(),
             options: (),
         }
@@ -222,95 +252,241 @@ impl<Options> Builder<Options> {
         }
     }
 
-    pub async fn launch<T: LanguageServer<Options>>(self) -> anyhow::Result<()> {
+    /// Runs the language server until the client sends `exit`.
+    ///
+    /// Returns [`ExitCode::FAILURE`] if `exit` was not preceded by `shutdown`.
+    pub async fn launch<T: LanguageServer<Options>>(self) -> anyhow::Result<ExitCode>
+    where
+        Options: 'static,
+    {
         let Self {
             connection,
             threads,
-            server_capabilities,
+            mut server_capabilities,
             options,
         } = self;
 
-        let params = connection.initialize(to_value(server_capabilities))?;
-        let params = from_value(params).context("deserializing initialization parameters")?;
+        let (id, params) = connection.initialize_start()?;
+        let params: InitializeParams =
+            from_value(params).context("deserializing initialization parameters")?;
+        let position_encoding = negotiate_position_encoding(&params);
+        server_capabilities.position_encoding = Some(position_encoding.clone());
+        connection.initialize_finish(
+            id,
+            to_value(InitializeResult {
+                capabilities: server_capabilities,
+                server_info: None,
+            }),
+        )?;
 
-        let imp = T::initialize(
-            params,
-            Client {
-                sender: connection.sender.clone(),
-            },
-            options,
-        )
-        .await?;
-        let imp = Arc::new(imp);
-
-        let c_receiver = connection.receiver.clone();
-        let (c_sender, mut receiver) = unbounded_channel();
-        thread::spawn(move || {
-            loop {
-                c_sender.send(c_receiver.recv().unwrap()).unwrap();
-            }
-        });
-        let runner = {
-            let sender = connection.sender.clone();
-            let imp = imp.clone();
-            tokio::spawn(async move {
-                let mut notifications = JoinSet::<()>::new();
-                // TODO request abortion
-                // let requests = HashMap::<RequestId, JoinHandle<()>>::new();
-
-                while let Some(message) = receiver.recv().await {
-                    info!("got message");
-                    let imp = imp.clone();
-                    let sender = sender.clone();
-                    match message {
-                        Message::Request(request) => {
-                            use lsp_types::request::*;
-                            match request.method.as_str() {
-                                Shutdown::METHOD => return Ok(request),
-                                _ => notifications.spawn(async move {
-                                    let (result, error) = imp
-                                        .handle_request(request.method, request.params)
-                                        .await
-                                        .split();
-                                    sender.send(Message::Response(Response {
-                                        id: request.id,
-                                        result,
-                                        error: error.map(|e| lsp_server::ResponseError {
-                                            code: 0,
-                                            message: e.to_string(),
-                                            data: None,
-                                        }),
-                                    }));
-                                }),
-                            };
-                        }
+        let work_done_progress = params
+            .capabilities
+            .window
+            .as_ref()
+            .and_then(|window| window.work_done_progress)
+            .unwrap_or_default();
+        let client = Client::new(
+            connection.sender.clone(),
+            position_encoding,
+            work_done_progress,
+        );
+        let receiver = forward_messages(connection.receiver.clone(), client.clone());
 
-                        Message::Response(_) => todo!(),
-                        Message::Notification(notification) => {
-                            notifications.spawn(async move {
-                                imp.handle_notification(notification.method, notification.params)
-                                    .await;
-                            });
-                        }
-                    }
-                }
-                bail!("channel disconnected prematurely")
-            })
-        };
+        let imp = Arc::new(T::initialize(params, client, options).await?);
+        let runner = tokio::spawn(dispatch::<Options, T>(
+            imp.clone(),
+            connection.sender.clone(),
+            receiver,
+        ));
 
-        let shutdown_req = runner.await??;
-        Arc::try_unwrap(imp)
+        let (shutdown_req, receiver) = runner.await??;
+        if let Err(e) = Arc::try_unwrap(imp)
             .ok()
             .expect("all futures are completed or aborted")
             .shutdown()
-            .await?;
-        assert!(
-            connection.handle_shutdown(&shutdown_req)?,
-            "should only return on shutdown_req"
-        );
-        threads.join().context("joining io threads")?;
-        Ok(())
+            .await
+        {
+            error!("{e}");
+        }
+        let Some(shutdown_req) = shutdown_req else {
+            error!("received exit notification without prior shutdown request");
+            return Ok(ExitCode::FAILURE);
+        };
+        await_exit(connection, threads, shutdown_req, receiver).await
+    }
+}
+
+type Receiver = tokio::sync::mpsc::UnboundedReceiver<Message>;
+
+/// Completes responses of `client` and forwards all other messages.
+///
+/// Responses are handled on their own thread, so requests also work during
+/// `initialize`.
+fn forward_messages(messages: crossbeam_channel::Receiver<Message>, client: Client) -> Receiver {
+    let (sender, receiver) = unbounded_channel();
+    thread::spawn(move || {
+        while let Ok(message) = messages.recv() {
+            if let Message::Response(response) = message {
+                client.complete_request(response);
+            } else if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Dispatches messages to `imp` until `shutdown` or `exit`, returning the
+/// shutdown request, if there was one, and the remaining messages.
+///
+/// Requests are handled concurrently, notifications in order.
+async fn dispatch<Options: 'static, T: LanguageServer<Options>>(
+    imp: Arc<T>,
+    sender: Sender<Message>,
+    mut receiver: Receiver,
+) -> anyhow::Result<(Option<lsp_server::Request>, Receiver)> {
+    use lsp_types::request::Shutdown;
+
+    let mut tasks = JoinSet::<()>::new();
+    // running requests, whoever removes a request is responsible for answering it
+    let requests = Arc::<RunningRequests>::default();
+
+    while let Some(message) = receiver.recv().await {
+        info!("got message");
+        match message {
+            Message::Request(request) if request.method == Shutdown::METHOD => {
+                // let running handlers finish, so they release `imp`
+                while tasks.join_next().await.is_some() {}
+                return Ok((Some(request), receiver));
+            }
+            Message::Request(request) => {
+                // otherwise the results of finished requests pile up until shutdown
+                while tasks.try_join_next().is_some() {}
+                spawn_request::<Options, T>(
+                    &mut tasks,
+                    &requests,
+                    imp.clone(),
+                    sender.clone(),
+                    request,
+                );
+            }
+            Message::Response(_) => {
+                unreachable!("responses are handled by the receiving thread")
+            }
+            Message::Notification(notification) if notification.method == Exit::METHOD => {
+                tasks.shutdown().await;
+                return Ok((None, receiver));
+            }
+            Message::Notification(notification) if notification.method == Cancel::METHOD => {
+                cancel_request(&requests, &sender, notification.params);
+            }
+            // handled one at a time, so text changes are applied in the order
+            // they were sent and never to a closed document
+            Message::Notification(notification) => {
+                imp.handle_notification(notification.method, notification.params)
+                    .await;
+            }
+        }
+    }
+    bail!("channel disconnected prematurely")
+}
+
+/// Handles `request` as one of `tasks`, registered in `requests` so it can be
+/// cancelled.
+fn spawn_request<Options, T: LanguageServer<Options>>(
+    tasks: &mut JoinSet<()>,
+    requests: &Arc<RunningRequests>,
+    imp: Arc<T>,
+    sender: Sender<Message>,
+    request: lsp_server::Request,
+) {
+    // hold the lock, so the request cannot complete before it is registered
+    let mut running = lock_requests(requests);
+    let id = request.id.clone();
+    let handle = tasks.spawn({
+        let requests = requests.clone();
+        async move {
+            let (result, error) = imp
+                .handle_request(request.method, request.params)
+                .await
+                .split();
+            if lock_requests(&requests).remove(&request.id).is_some() {
+                sender.send(Message::Response(Response {
+                    id: request.id,
+                    result,
+                    error: error.map(Into::into),
+                }));
+            }
+        }
+    });
+    running.insert(id, handle);
+}
+
+/// Aborts the running request cancelled by `params` and answers it.
+fn cancel_request(requests: &RunningRequests, sender: &Sender<Message>, params: Value) {
+    match from_value::<CancelParams>(params) {
+        Ok(CancelParams { id }) => {
+            let id = match id {
+                NumberOrString::Number(id) => RequestId::from(id),
+                NumberOrString::String(id) => RequestId::from(id),
+            };
+            // unknown ids belong to already answered requests
+            if let Some(handle) = lock_requests(requests).remove(&id) {
+                handle.abort();
+                sender.send(Message::Response(Response::new_err(
+                    id,
+                    ErrorCode::RequestCancelled as i32,
+                    "request was cancelled".to_owned(),
+                )));
+            }
+        }
+        Err(e) => error!("{e}"),
+    }
+}
+
+/// Answers the `shutdown` request and waits for the `exit` notification,
+/// rejecting all requests in between.
+async fn await_exit(
+    connection: Connection,
+    threads: Option<IoThreads>,
+    shutdown: lsp_server::Request,
+    mut receiver: Receiver,
+) -> anyhow::Result<ExitCode> {
+    connection
+        .sender
+        .send(Message::Response(Response::new_ok(shutdown.id, ())))?;
+
+    // after `shutdown` the only valid message is `exit`
+    while let Some(message) = receiver.recv().await {
+        match message {
+            Message::Notification(notification) if notification.method == Exit::METHOD => {
+                drop(connection);
+                if let Some(threads) = threads {
+                    threads.join().context("joining io threads")?;
+                }
+                return Ok(ExitCode::SUCCESS);
+            }
+            Message::Request(request) => {
+                connection.sender.send(Message::Response(Response::new_err(
+                    request.id,
+                    ErrorCode::InvalidRequest as i32,
+                    "server is shutting down".to_owned(),
+                )))?;
+            }
+            Message::Notification(_) | Message::Response(_) => {}
+        }
     }
+    bail!("channel disconnected before exit notification")
+}
+
+type RunningRequests = std::sync::Mutex<HashMap<RequestId, AbortHandle>>;
+
+fn lock_requests(
+    requests: &RunningRequests,
+) -> std::sync::MutexGuard<'_, HashMap<RequestId, AbortHandle>> {
+    requests
+        .lock()
+        .expect("requests lock should not be poisoned")
 }
 
 #[ext]
@@ -323,12 +499,121 @@ impl<T, E> Result<T, E> {
     }
 }
 
+/// Picks UTF-8 if the client supports it, as it matches the internal
+/// representation, otherwise the mandatory UTF-16.
+fn negotiate_position_encoding(params: &InitializeParams) -> PositionEncodingKind {
+    let supported = params
+        .capabilities
+        .general
+        .as_ref()
+        .and_then(|general| general.position_encodings.as_ref());
+    if supported.is_some_and(|supported| supported.contains(&PositionEncodingKind::UTF8)) {
+        PositionEncodingKind::UTF8
+    } else {
+        PositionEncodingKind::UTF16
+    }
+}
+
 #[derive(Clone)]
 pub struct Client {
     sender: Sender<Message>,
+    next_request_id: Arc<AtomicI32>,
+    pending_requests: Arc<std::sync::Mutex<HashMap<RequestId, oneshot::Sender<Response>>>>,
+    position_encoding: PositionEncodingKind,
+    work_done_progress: bool,
 }
 
 impl Client {
+    fn new(
+        sender: Sender<Message>,
+        position_encoding: PositionEncodingKind,
+        work_done_progress: bool,
+    ) -> Self {
+        Self {
+            sender,
+            next_request_id: Arc::default(),
+            pending_requests: Arc::default(),
+            position_encoding,
+            work_done_progress,
+        }
+    }
+
+    /// Encoding of [`Position::character`](lsp_types::Position::character)
+    /// negotiated during initialization.
+    pub fn position_encoding(&self) -> &PositionEncodingKind {
+        &self.position_encoding
+    }
+
+    /// Hands a response to the corresponding [`Client::send_request`].
+    fn complete_request(&self, response: Response) {
+        let pending = self
+            .pending_requests
+            .lock()
+            .expect("pending requests lock should not be poisoned")
+            .remove(&response.id);
+        if let Some(pending) = pending {
+            _ = pending.send(response);
+        } else {
+            warn!("got response to unknown request `{}`", response.id);
+        }
+    }
+
+    pub async fn send_request<R: Request>(&self, params: R::Params) -> Result<R::Result> {
+        let id = RequestId::from(self.next_request_id.fetch_add(1, Ordering::Relaxed));
+        let (sender, receiver) = oneshot::channel();
+        self.pending_requests
+            .lock()
+            .expect("pending requests lock should not be poisoned")
+            .insert(id.clone(), sender);
+        self.sender
+            .send(Message::Request(lsp_server::Request {
+                id,
+                method: R::METHOD.to_owned(),
+                params: to_value(params),
+            }))
+            .unwrap();
+        let response = receiver
+            .await
+            .internal_error(format!("no response to `{}`", R::METHOD))?;
+        if let Some(error) = response.error {
+            return Err(request_failed!(
+                "`{}` failed ({}): {}",
+                R::METHOD,
+                error.code,
+                error.message
+            ));
+        }
+        from_value(response.result.unwrap_or_default())
+    }
+
+    /// Shows a message with `actions` to choose from, returns the chosen one.
+    pub async fn show_message_request(
+        &self,
+        typ: MessageType,
+        message: impl Into<String>,
+        actions: Vec<MessageActionItem>,
+    ) -> Result<Option<MessageActionItem>> {
+        self.send_request::<ShowMessageRequest>(ShowMessageRequestParams {
+            typ,
+            message: message.into(),
+            actions: Some(actions),
+        })
+        .await
+    }
+
+    /// Asks the client to apply `edit`, the response states whether it did.
+    pub async fn apply_edit(
+        &self,
+        label: impl Into<String>,
+        edit: WorkspaceEdit,
+    ) -> Result<ApplyWorkspaceEditResponse> {
+        self.send_request::<ApplyWorkspaceEdit>(ApplyWorkspaceEditParams {
+            label: Some(label.into()),
+            edit,
+        })
+        .await
+    }
+
     pub fn publish_diagnostics(&self, uri: Url, diagnostics: Vec<Diagnostic>) {
         self.send_notification::<PublishDiagnostics>(PublishDiagnosticsParams {
             uri,
@@ -337,6 +622,77 @@ impl Client {
         });
     }
 
+    /// Creates a progress and reports its begin, `None` if the client does not
+    /// support work done progress, refused to create it or did not respond
+    /// in time.
+    pub async fn begin_progress(&self, title: impl Into<String>) -> Option<ProgressToken> {
+        if !self.work_done_progress {
+            return None;
+        }
+        let token = ProgressToken::String(format!(
+            "doc-spelling-lsp/{}",
+            self.next_request_id.fetch_add(1, Ordering::Relaxed)
+        ));
+        let create = self.send_request::<WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+            token: token.clone(),
+        });
+        match tokio::time::timeout(PROGRESS_CREATE_TIMEOUT, create).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                warn!("unable to create progress: {}", e.message);
+                return None;
+            }
+            Err(_) => {
+                warn!("client did not create progress in time");
+                return None;
+            }
+        }
+        self.send_progress(
+            token.clone(),
+            WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: title.into(),
+                ..WorkDoneProgressBegin::default()
+            }),
+        );
+        Some(token)
+    }
+
+    /// Reports the end of a progress created by [`Client::begin_progress`].
+    pub fn end_progress(&self, token: ProgressToken, message: Option<String>) {
+        self.send_progress(
+            token,
+            WorkDoneProgress::End(WorkDoneProgressEnd { message }),
+        );
+    }
+
+    /// Reports `progress` of `token`, ignored if the client does not support
+    /// work done progress.
+    pub fn send_progress(&self, token: ProgressToken, progress: WorkDoneProgress) {
+        if self.work_done_progress {
+            self.send_notification::<Progress>(ProgressParams {
+                token,
+                value: ProgressParamsValue::WorkDone(progress),
+            });
+        }
+    }
+
+    /// Shows a message to the user, see [`Client::log_message`] for messages
+    /// that should not interrupt.
+    pub fn show_message(&self, typ: MessageType, message: impl Into<String>) {
+        self.send_notification::<ShowMessage>(ShowMessageParams {
+            typ,
+            message: message.into(),
+        });
+    }
+
+    /// Logs a message in the client.
+    pub fn log_message(&self, typ: MessageType, message: impl Into<String>) {
+        self.send_notification::<LogMessage>(LogMessageParams {
+            typ,
+            message: message.into(),
+        });
+    }
+
     pub fn send_notification<N: Notification>(&self, params: N::Params) {
         self.sender
             .send(Message::Notification(lsp_server::Notification {
@@ -344,7 +700,7 @@ impl Client {
                 params: to_value(params),
             }))
             .unwrap();
-        info!("send diagnostics");
+        info!("sent {}", N::METHOD);
     }
 }
 
@@ -360,6 +716,8 @@ pub trait LanguageServer<Options = ()>: Sized + Send + Sync + 'static {
     async fn handle_request(&self, method: String, params: Value) -> Result<Value> {
         forr! {($request:ty, $method:ty) in [
             (CodeActionRequest, code_action), (ExecuteCommand, execute_command),
+            (DocumentDiagnosticRequest, document_diagnostic), (HoverRequest, hover),
+            (Completion, completion),
         ] $:
             match method.as_str() {
                 $(lsp_types::request::$request::METHOD => self.$method(from_value(params)?).await.map(to_value),)*
@@ -375,7 +733,8 @@ pub trait LanguageServer<Options = ()>: Sized + Send + Sync + 'static {
     async fn handle_notification(&self, method: String, params: Value) {
         info!("handling {method:?} {params:?}");
         forr! {($request:ty, $method:ty) in [
-            (DidChangeTextDocument, did_change), (DidOpenTextDocument, did_open), (DidSaveTextDocument, did_save)
+            (DidChangeTextDocument, did_change), (DidOpenTextDocument, did_open), (DidSaveTextDocument, did_save),
+            (DidCloseTextDocument, did_close), (DidChangeConfiguration, did_change_configuration)
         ] $:
             match method.as_str() {
                 $(lsp_types::notification::$request::METHOD => match from_value(params) {
@@ -396,6 +755,8 @@ pub trait LanguageServer<Options = ()>: Sized + Send + Sync + 'static {
     async fn did_change(&self, params: lsp_types::DidChangeTextDocumentParams) {}
     async fn did_open(&self, params: lsp_types::DidOpenTextDocumentParams) {}
     async fn did_save(&self, params: lsp_types::DidSaveTextDocumentParams) {}
+    async fn did_close(&self, params: lsp_types::DidCloseTextDocumentParams) {}
+    async fn did_change_configuration(&self, params: lsp_types::DidChangeConfigurationParams) {}
 
     // requests
     async fn code_action(
@@ -412,4 +773,490 @@ pub trait LanguageServer<Options = ()>: Sized + Send + Sync + 'static {
         warn!("Got a workspace/executeCommand request, but it is not implemented");
         Err(method_not_found!())
     }
+    async fn document_diagnostic(
+        &self,
+        params: lsp_types::DocumentDiagnosticParams,
+    ) -> Result<lsp_types::DocumentDiagnosticReportResult> {
+        warn!("Got a textDocument/diagnostic request, but it is not implemented");
+        Err(method_not_found!())
+    }
+    async fn hover(&self, params: lsp_types::HoverParams) -> Result<Option<lsp_types::Hover>> {
+        warn!("Got a textDocument/hover request, but it is not implemented");
+        Err(method_not_found!())
+    }
+    async fn completion(
+        &self,
+        params: lsp_types::CompletionParams,
+    ) -> Result<Option<lsp_types::CompletionResponse>> {
+        warn!("Got a textDocument/completion request, but it is not implemented");
+        Err(method_not_found!())
+    }
+}
+
+/// Editor side of a connection, to test language servers and the [`Client`].
+#[cfg(test)]
+pub mod testing {
+    use lsp_types::notification::Initialized;
+    use lsp_types::request::{Initialize, Shutdown};
+    use lsp_types::InitializedParams;
+
+    use super::*;
+
+    /// Time to wait for a message from the server before failing.
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Runs the language server `T` until `editor`, running on another thread,
+    /// sends `exit`.
+    pub async fn run<T: LanguageServer>(
+        editor: impl FnOnce(TestClient) + Send + 'static,
+    ) -> ExitCode {
+        let (server, client) = Connection::memory();
+        let editor = thread::spawn(move || editor(TestClient::new(client)));
+        let exit_code = Builder::connection(server).launch::<T>().await;
+        editor.join().expect("editor should not panic");
+        exit_code.expect("server should exit")
+    }
+
+    pub struct TestClient {
+        connection: Connection,
+        next_id: i32,
+        /// Results of requests from the server by method, `null` for other
+        /// methods.
+        pub results: HashMap<&'static str, Value>,
+        /// Messages from the server that were not waited for.
+        pub received: Vec<Message>,
+    }
+
+    impl TestClient {
+        pub fn new(connection: Connection) -> Self {
+            Self {
+                connection,
+                next_id: 0,
+                results: HashMap::new(),
+                received: Vec::new(),
+            }
+        }
+
+        /// [`Client`] connected to a test client, without a server.
+        pub fn client() -> (Client, Self) {
+            let (server, editor) = Connection::memory();
+            let client = Client::new(server.sender, PositionEncodingKind::UTF16, true);
+            {
+                let client = client.clone();
+                thread::spawn(move || {
+                    for message in server.receiver {
+                        if let Message::Response(response) = message {
+                            client.complete_request(response);
+                        }
+                    }
+                });
+            }
+            (client, Self::new(editor))
+        }
+
+        /// Next message from the server, requests are answered with their
+        /// [result](TestClient::results).
+        pub fn receive(&mut self) -> Message {
+            let message = self
+                .connection
+                .receiver
+                .recv_timeout(TIMEOUT)
+                .expect("server should send a message");
+            if let Message::Request(request) = &message {
+                let result = self
+                    .results
+                    .get(request.method.as_str())
+                    .cloned()
+                    .unwrap_or_default();
+                self.connection
+                    .sender
+                    .send(Message::Response(Response::new_ok(
+                        request.id.clone(),
+                        result,
+                    )))
+                    .expect("server should be connected");
+            }
+            message
+        }
+
+        /// Receives messages until the notification `N`, returns its params.
+        pub fn notification<N: Notification>(&mut self) -> N::Params {
+            loop {
+                match self.receive() {
+                    Message::Notification(notification) if notification.method == N::METHOD => {
+                        return from_value(notification.params).expect("params should be valid");
+                    }
+                    message => self.received.push(message),
+                }
+            }
+        }
+
+        /// Receives messages until the request `R`, answers and returns it.
+        pub fn server_request<R: Request>(&mut self) -> R::Params {
+            loop {
+                match self.receive() {
+                    Message::Request(request) if request.method == R::METHOD => {
+                        return from_value(request.params).expect("params should be valid");
+                    }
+                    message => self.received.push(message),
+                }
+            }
+        }
+
+        /// Sends the request `R` and waits for the response.
+        pub fn request<R: Request>(&mut self, params: R::Params) -> Response {
+            let id = self.send_request::<R>(params);
+            self.response(&id)
+        }
+
+        /// Sends the request `R` without waiting, returns the id of its
+        /// [response](TestClient::response).
+        pub fn send_request<R: Request>(&mut self, params: R::Params) -> RequestId {
+            let id = RequestId::from(self.next_id);
+            self.next_id += 1;
+            self.connection
+                .sender
+                .send(Message::Request(lsp_server::Request {
+                    id: id.clone(),
+                    method: R::METHOD.to_owned(),
+                    params: to_value(params),
+                }))
+                .expect("server should be connected");
+            id
+        }
+
+        /// Receives messages until the response to the request `id`.
+        pub fn response(&mut self, id: &RequestId) -> Response {
+            loop {
+                match self.receive() {
+                    Message::Response(response) if response.id == *id => return response,
+                    message => self.received.push(message),
+                }
+            }
+        }
+
+        pub fn notify<N: Notification>(&self, params: N::Params) {
+            self.connection
+                .sender
+                .send(Message::Notification(lsp_server::Notification {
+                    method: N::METHOD.to_owned(),
+                    params: to_value(params),
+                }))
+                .expect("server should be connected");
+        }
+
+        /// Sends `initialize` with `params` followed by `initialized`.
+        pub fn initialize(&mut self, params: InitializeParams) -> InitializeResult {
+            let response = self.request::<Initialize>(params);
+            self.notify::<Initialized>(InitializedParams {});
+            from_value(response.result.expect("initialize should succeed"))
+                .expect("result should be valid")
+        }
+
+        /// Sends `shutdown` followed by `exit`.
+        pub fn shutdown(&mut self) {
+            let response = self.request::<Shutdown>(());
+            assert!(response.error.is_none(), "{:?}", response.error);
+            self.notify::<Exit>(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::notification::DidOpenTextDocument;
+    use lsp_types::request::{HoverRequest, Shutdown};
+    use lsp_types::{
+        ClientCapabilities, DidChangeTextDocumentParams, DidOpenTextDocumentParams,
+        GeneralClientCapabilities, HoverContents, HoverParams, MarkedString, Position,
+        TextDocumentContentChangeEvent, TextDocumentIdentifier, TextDocumentItem,
+        TextDocumentPositionParams, VersionedTextDocumentIdentifier,
+    };
+    use serde_json::json;
+
+    use super::*;
+
+    struct Server;
+
+    #[async_trait::async_trait]
+    impl LanguageServer for Server {
+        async fn initialize(
+            _params: InitializeParams,
+            _client: Client,
+            _options: (),
+        ) -> Result<Self> {
+            Ok(Self)
+        }
+
+        async fn shutdown(self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Server keeping the text of open documents, returned on hover.
+    #[derive(Default)]
+    struct Documents(tokio::sync::Mutex<HashMap<Url, String>>);
+
+    #[async_trait::async_trait]
+    impl LanguageServer for Documents {
+        async fn initialize(
+            _params: InitializeParams,
+            _client: Client,
+            _options: (),
+        ) -> Result<Self> {
+            Ok(Self::default())
+        }
+
+        async fn shutdown(self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn did_open(&self, params: DidOpenTextDocumentParams) {
+            self.0
+                .lock()
+                .await
+                .insert(params.text_document.uri, params.text_document.text);
+        }
+
+        async fn did_change(&self, params: DidChangeTextDocumentParams) {
+            // earlier changes take longer, so handling them concurrently would
+            // apply them out of order
+            let remaining = 10_u32.saturating_sub(params.text_document.version.unsigned_abs());
+            tokio::time::sleep(Duration::from_millis(u64::from(remaining) * 10)).await;
+            let mut documents = self.0.lock().await;
+            let Some(text) = documents.get_mut(&params.text_document.uri) else {
+                return;
+            };
+            for change in params.content_changes {
+                if let Some(range) = change.range {
+                    crate::diagnostic::apply_change(
+                        text,
+                        &PositionEncodingKind::UTF16,
+                        range,
+                        &change.text,
+                    );
+                } else {
+                    *text = change.text;
+                }
+            }
+        }
+
+        async fn hover(&self, params: HoverParams) -> Result<Option<lsp_types::Hover>> {
+            let uri = params.text_document_position_params.text_document.uri;
+            Ok(self.0.lock().await.get(&uri).map(|text| lsp_types::Hover {
+                contents: HoverContents::Scalar(MarkedString::String(text.clone())),
+                range: None,
+            }))
+        }
+    }
+
+    /// Server whose hovers never finish, so they can only be cancelled.
+    struct Pending;
+
+    #[async_trait::async_trait]
+    impl LanguageServer for Pending {
+        async fn initialize(
+            _params: InitializeParams,
+            _client: Client,
+            _options: (),
+        ) -> Result<Self> {
+            Ok(Self)
+        }
+
+        async fn shutdown(self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn hover(&self, _params: HoverParams) -> Result<Option<lsp_types::Hover>> {
+            std::future::pending().await
+        }
+    }
+
+    fn hover() -> HoverParams {
+        HoverParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: Url::parse("file:///lib.rs").unwrap(),
+                },
+                position: Position::new(0, 0),
+            },
+            work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn exits_successfully_after_shutdown() {
+        let exit_code = testing::run::<Server>(|mut editor| {
+            let result = editor.initialize(InitializeParams::default());
+            assert_eq!(
+                result.capabilities.position_encoding,
+                Some(PositionEncodingKind::UTF16)
+            );
+            let response = editor.request::<HoverRequest>(hover());
+            assert_eq!(
+                response.error.map(|error| error.code),
+                Some(ErrorCode::MethodNotFound as i32)
+            );
+            let response = editor.request::<Shutdown>(());
+            assert!(response.error.is_none());
+            let response = editor.request::<HoverRequest>(hover());
+            assert_eq!(
+                response.error.map(|error| error.code),
+                Some(ErrorCode::InvalidRequest as i32)
+            );
+            editor.notify::<Exit>(());
+        })
+        .await;
+        assert_eq!(exit_code, ExitCode::SUCCESS);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn applies_text_changes_in_order() {
+        let exit_code = testing::run::<Documents>(|mut editor| {
+            editor.initialize(InitializeParams::default());
+            let uri = Url::parse("file:///lib.rs").unwrap();
+            editor.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    uri.clone(),
+                    "rust".to_owned(),
+                    0,
+                    String::new(),
+                ),
+            });
+            // each change appends to the result of the previous one
+            for (character, text) in (0..).zip(["a", "b", "c", "d", "e"]) {
+                editor.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+                    text_document: VersionedTextDocumentIdentifier::new(
+                        uri.clone(),
+                        i32::try_from(character + 1).unwrap(),
+                    ),
+                    content_changes: vec![TextDocumentContentChangeEvent {
+                        range: Some(lsp_types::Range::new(
+                            Position::new(0, character),
+                            Position::new(0, character),
+                        )),
+                        range_length: None,
+                        text: text.to_owned(),
+                    }],
+                });
+            }
+            let response = editor.request::<HoverRequest>(hover());
+            let hover: lsp_types::Hover = from_value(response.result.unwrap()).unwrap();
+            assert_eq!(
+                hover.contents,
+                HoverContents::Scalar(MarkedString::String("abcde".to_owned()))
+            );
+            editor.shutdown();
+        })
+        .await;
+        assert_eq!(exit_code, ExitCode::SUCCESS);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn answers_cancelled_requests() {
+        let exit_code = testing::run::<Pending>(|mut editor| {
+            editor.initialize(InitializeParams::default());
+            let id = editor.send_request::<HoverRequest>(hover());
+            editor.notify::<Cancel>(CancelParams {
+                id: from_value(to_value(&id)).unwrap(),
+            });
+            let response = editor.response(&id);
+            assert_eq!(
+                response.error.map(|error| error.code),
+                Some(ErrorCode::RequestCancelled as i32)
+            );
+            // the cancelled request does not block the shutdown
+            editor.shutdown();
+        })
+        .await;
+        assert_eq!(exit_code, ExitCode::SUCCESS);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fails_on_exit_without_shutdown() {
+        let exit_code = testing::run::<Server>(|mut editor| {
+            editor.initialize(InitializeParams::default());
+            editor.notify::<Exit>(());
+        })
+        .await;
+        assert_eq!(exit_code, ExitCode::FAILURE);
+    }
+
+    #[test]
+    fn sends_messages_as_notifications() {
+        let (client, mut editor) = testing::TestClient::client();
+        client.show_message(MessageType::WARNING, "shown");
+        client.log_message(MessageType::INFO, "logged");
+        assert_eq!(editor.notification::<ShowMessage>(), ShowMessageParams {
+            typ: MessageType::WARNING,
+            message: "shown".to_owned(),
+        });
+        assert_eq!(editor.notification::<LogMessage>(), LogMessageParams {
+            typ: MessageType::INFO,
+            message: "logged".to_owned(),
+        });
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn apply_edit_returns_the_response() {
+        let (client, mut editor) = testing::TestClient::client();
+        let editor = thread::spawn(move || {
+            editor
+                .results
+                .insert(ApplyWorkspaceEdit::METHOD, json!({ "applied": true }));
+            editor.server_request::<ApplyWorkspaceEdit>()
+        });
+        let response = client
+            .apply_edit("Fix all", WorkspaceEdit::default())
+            .await
+            .unwrap();
+        assert!(response.applied);
+        let params = editor.join().unwrap();
+        assert_eq!(params.label.as_deref(), Some("Fix all"));
+    }
+
+    /// Initialization of a client supporting UTF-16 and UTF-8 positions.
+    fn supporting_utf8() -> InitializeParams {
+        InitializeParams {
+            capabilities: ClientCapabilities {
+                general: Some(GeneralClientCapabilities {
+                    position_encodings: Some(vec![
+                        PositionEncodingKind::UTF16,
+                        PositionEncodingKind::UTF8,
+                    ]),
+                    ..GeneralClientCapabilities::default()
+                }),
+                ..ClientCapabilities::default()
+            },
+            ..InitializeParams::default()
+        }
+    }
+
+    #[test]
+    fn negotiates_utf8_if_supported() {
+        let mut params = supporting_utf8();
+        assert_eq!(
+            negotiate_position_encoding(&params),
+            PositionEncodingKind::UTF8
+        );
+        params.capabilities.general = None;
+        assert_eq!(
+            negotiate_position_encoding(&params),
+            PositionEncodingKind::UTF16
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn announces_negotiated_encoding() {
+        let exit_code = testing::run::<Server>(|mut editor| {
+            let result = editor.initialize(supporting_utf8());
+            assert_eq!(
+                result.capabilities.position_encoding,
+                Some(PositionEncodingKind::UTF8)
+            );
+            editor.shutdown();
+        })
+        .await;
+        assert_eq!(exit_code, ExitCode::SUCCESS);
+    }
 }
@@ -1,33 +1,49 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::missing_errors_doc, clippy::wildcard_imports)]
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env::{self};
-use std::fs::File;
-use std::process::{Child, Command};
-use std::sync::Arc;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitCode};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, Weak};
+use std::time::Duration;
+use std::{future, io};
 
 use derive_more::{Display, FromStr};
 use languagetool_rust::ServerClient;
-use log::{error, info};
+use log::{error, info, warn};
+use lsp_types::notification::Notification;
+use lsp_types::request::WorkspaceDiagnosticRefresh;
 use lsp_types::{
-    CodeAction, CodeActionKind, CodeActionOrCommand, DocumentChanges, OneOf,
+    CodeAction, CodeActionKind, CodeActionOrCommand, DocumentChanges, MessageType, OneOf,
     OptionalVersionedTextDocumentIdentifier, TextDocumentEdit, Url,
 };
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use state::State;
+use state::{State, AUTO_LANGUAGE, DEFAULT_LANGUAGE};
 use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout_at, Instant};
 
-use self::diagnostic::diagnose;
+use self::diagnostic::{
+    cache_location, diagnose, diagnose_range, diagnose_with_progress, Checker, RateLimited,
+    DIRECTIVE_PREFIX,
+};
 use self::lsp::{Builder, Client, Context, LanguageServer, Result};
 
+mod check;
 mod config;
 mod diagnostic;
+mod line_index;
 mod lsp;
+mod shared;
 mod state;
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 10)]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
     let log_file = env::var("RUST_LOG_FILE").map(|file| File::create(file).unwrap());
     env_logger::builder()
         .target(if let Ok(log_file) = log_file {
@@ -38,15 +54,35 @@ async fn main() -> anyhow::Result<()> {
         .init();
     embedded_language_tool::handle_extraction();
 
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        Some("check") => return Ok(check::run(args).await),
+        Some("--config-schema") => {
+            println!("{}", config::schema());
+            return Ok(ExitCode::SUCCESS);
+        }
+        _ => {}
+    }
+
     Builder::stdio()
         .server_capabilities({
             use lsp_types::*;
             ServerCapabilities {
-                // TODO: support partial updates
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                    TextDocumentSyncKind::INCREMENTAL,
                 )),
                 code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                completion_provider: Some(CompletionOptions {
+                    trigger_characters: Some(vec![":".into(), "=".into()]),
+                    ..Default::default()
+                }),
+                diagnostic_provider: Some(DiagnosticServerCapabilities::Options(
+                    DiagnosticOptions {
+                        identifier: Some("doc-spelling-lsp".into()),
+                        ..Default::default()
+                    },
+                )),
                 execute_command_provider: Some(ExecuteCommandOptions {
                     commands: WorkspaceCommand::options(),
                     ..Default::default()
@@ -63,49 +99,1602 @@ struct InitializedLsp {}
 
 struct Lsp {
     client: Client,
-    ltex_server: Option<Child>,
-    documents: Arc<Mutex<HashMap<Url, String>>>,
-    diagnose: watch::Sender<HashSet<Url>>,
-    state: watch::Sender<state::State>,
+    /// `None` if no server was spawned or after shutdown.
+    ltex_server: Arc<std::sync::Mutex<Option<ServerProcess>>>,
+    checker: Arc<Checker>,
+    documents: Arc<Mutex<HashMap<Url, Document>>>,
+    /// Last diagnostics of each document, to show their details on hover.
+    diagnostics: Arc<Mutex<HashMap<Url, Vec<lsp_types::Diagnostic>>>>,
+    diagnose: Arc<watch::Sender<Queue>>,
+    state: Arc<watch::Sender<state::State>>,
+    /// Writes changes of the state, finishes once `state` is dropped, `None` if
+    /// the state is only kept in memory.
+    state_writer: Option<JoinHandle<()>>,
+    /// `None` without a workspace root.
+    workspace_dictionary: Option<PathBuf>,
+    config: Arc<watch::Sender<config::Config>>,
+    /// Settings of the editor, layered over the [`config::project_file`].
+    settings: Arc<std::sync::Mutex<Option<Value>>>,
+    /// `None` without a workspace root.
+    project_config: Option<PathBuf>,
+    /// Whether the client pulls diagnostics, instead of them being published.
+    pull_diagnostics: bool,
+    /// Incremented whenever diagnostics might change, used as `resultId` of
+    /// pulled diagnostics.
+    revision: Arc<AtomicU64>,
+    /// Rules already suggested to be disabled in this session.
+    noisy_rules: Arc<std::sync::Mutex<HashSet<String>>>,
+}
+
+#[derive(Clone, Default)]
+struct Document {
+    text: String,
+    language_id: String,
 }
 
 impl Lsp {
+    /// Open document at `uri`, with the state and diagnostics configuration to
+    /// check it with.
+    async fn checked_document(&self, uri: &Url) -> Result<(Document, State, config::Diagnostics)> {
+        let document = self
+            .documents
+            .lock()
+            .await
+            .get(uri)
+            .cloned()
+            .invalid_params(format!("unknown document `{uri}`"))?;
+        let config = self.config.borrow();
+        let state = self
+            .state
+            .borrow()
+            .for_document(&config, uri, &document.language_id);
+        let diagnostics = config.diagnostics.clone();
+        Ok((document, state, diagnostics))
+    }
+
     fn publish_diagnostics(&self, uri: Url) {
-        self.diagnose.send_modify(|s| _ = s.insert(uri));
+        self.revision.fetch_add(1, Ordering::Relaxed);
+        if self.pull_diagnostics {
+            return;
+        }
+        self.diagnose.send_modify(|queue| {
+            queue.delayed.remove(&uri);
+            queue.documents.insert(uri);
+        });
+    }
+
+    /// Publishes diagnostics once the document was not changed for
+    /// `diagnostics.delay_ms`, so typing does not trigger a check per
+    /// keystroke.
+    fn publish_diagnostics_delayed(&self, uri: Url) {
+        let delay = Duration::from_millis(self.config.borrow().diagnostics.delay_ms);
+        if delay.is_zero() || self.pull_diagnostics {
+            self.publish_diagnostics(uri);
+            return;
+        }
+        self.diagnose.send_modify(|queue| {
+            // the edited document is most likely the one in focus
+            queue.focused = Some(uri.clone());
+            if !queue.documents.contains(&uri) {
+                queue.delayed.insert(uri, Instant::now() + delay);
+            }
+        });
+    }
+
+    async fn publish_all_diagnostics(&self) {
+        self.revision.fetch_add(1, Ordering::Relaxed);
+        if self.pull_diagnostics {
+            refresh_diagnostics(&self.client);
+            return;
+        }
+        let documents = self.documents.lock().await;
+        self.diagnose
+            .send_modify(|queue| queue.documents.extend(documents.keys().cloned()));
+    }
+}
+
+/// Applies `config`, keeping and warning about the values that require a
+/// restart.
+fn apply_config(
+    config_sender: &watch::Sender<config::Config>,
+    state: &watch::Sender<State>,
+    client: &Client,
+    mut config: config::Config,
+) {
+    let current = config_sender.borrow().clone();
+    // these are only used during initialization
+    let mut requires_restart = Vec::new();
+    if config.server != current.server {
+        requires_restart.push("server");
+        config.server = current.server;
+    }
+    if config.state.location != current.state.location {
+        requires_restart.push("state.location");
+        config.state.location = current.state.location;
+    }
+    if config.state.scope != current.state.scope {
+        requires_restart.push("state.scope");
+        config.state.scope = current.state.scope;
+    }
+    if config.diagnostics.cache_size != current.diagnostics.cache_size {
+        requires_restart.push("diagnostics.cache_size");
+        config.diagnostics.cache_size = current.diagnostics.cache_size;
+    }
+    if config.diagnostics.max_concurrent_checks != current.diagnostics.max_concurrent_checks {
+        requires_restart.push("diagnostics.max_concurrent_checks");
+        config.diagnostics.max_concurrent_checks = current.diagnostics.max_concurrent_checks;
+    }
+    if !requires_restart.is_empty() {
+        let message = format!(
+            "changes to `{}` require a restart to take effect",
+            requires_restart.join("`, `")
+        );
+        warn!("{message}");
+        client.show_message(MessageType::WARNING, message);
+    }
+    if config.state.dictionary_files != current.state.dictionary_files {
+        let external_dictionary = state::load_dictionaries(&config.state.dictionary_files);
+        state.send_modify(|state| state.external_dictionary = external_dictionary);
+    }
+    config_sender.send_replace(config);
+}
+
+/// Applies the editor `settings` layered over `project_config`, keeping the
+/// current config and settings if they are invalid or empty.
+///
+/// Returns whether the config was applied.
+fn apply_settings(
+    settings: Value,
+    current_settings: &std::sync::Mutex<Option<Value>>,
+    project_config: Option<&Path>,
+    config_sender: &watch::Sender<config::Config>,
+    state: &watch::Sender<State>,
+    client: &Client,
+) -> bool {
+    // clients without settings for this server send `null` or `{}`, which
+    // would otherwise replace the initialization options
+    if settings.is_null() || settings.as_object().is_some_and(serde_json::Map::is_empty) {
+        return false;
+    }
+    match config::load(Some(settings.clone()), project_config) {
+        Ok(config) => {
+            *current_settings
+                .lock()
+                .expect("settings lock should not be poisoned") = Some(settings);
+            apply_config(config_sender, state, client, config);
+            true
+        }
+        Err(e) => {
+            error!("invalid config: {e}");
+            client.show_message(
+                MessageType::ERROR,
+                format!("unable to apply configuration: {e}"),
+            );
+            false
+        }
+    }
+}
+
+/// Applies changes to `project_config` until `config` is dropped, calling
+/// `on_reload` afterwards.
+fn reload_project_config(
+    config: Weak<watch::Sender<config::Config>>,
+    state: Weak<watch::Sender<State>>,
+    settings: Arc<std::sync::Mutex<Option<Value>>>,
+    project_config: PathBuf,
+    client: Client,
+    on_reload: impl Fn() + Send + 'static,
+) {
+    state::poll_file(project_config, config, move |config_sender, file| {
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        let settings = settings
+            .lock()
+            .expect("settings lock should not be poisoned")
+            .clone();
+        match config::load(settings, Some(file)) {
+            Ok(config) => {
+                apply_config(config_sender, &state, &client, config);
+                on_reload();
+            }
+            Err(e) => {
+                error!("invalid config: {e}");
+                client.show_message(
+                    MessageType::ERROR,
+                    format!("unable to apply configuration: {e}"),
+                );
+            }
+        }
+    });
+}
+
+/// Whether the first replacement can be applied without looking at it, i.e.,
+/// the diagnostic is a misspelling in prose.
+///
+/// Renaming single occurrences of an identifier would break the code.
+fn is_safe_fix(meta: &diagnostic::Meta) -> bool {
+    meta.missspelled.is_some() && meta.identifier.is_none()
+}
+
+/// First replacement of each [safe](is_safe_fix) diagnostic, without
+/// overlapping edits.
+fn fix_all_edits(
+    diagnostics: impl IntoIterator<Item = lsp_types::Diagnostic>,
+) -> Vec<lsp_types::TextEdit> {
+    let mut edits: Vec<_> = diagnostics
+        .into_iter()
+        .filter_map(|diagnostic| {
+            let meta: diagnostic::Meta = serde_json::from_value(diagnostic.data?).ok()?;
+            if !is_safe_fix(&meta) {
+                return None;
+            }
+            Some(lsp_types::TextEdit {
+                range: diagnostic.range,
+                new_text: meta.replacements.into_iter().next()?,
+            })
+        })
+        .collect();
+    edits.sort_by_key(|edit| (edit.range.start, edit.range.end));
+    // overlapping edits are invalid, keep the first one
+    edits.dedup_by(|edit, previous| edit.range.start < previous.range.end);
+    edits
+}
+
+/// Source action applying the [`fix_all_edits`] of `diagnostics`.
+fn fix_all_action(uri: &Url, diagnostics: &[lsp_types::Diagnostic]) -> Option<CodeActionOrCommand> {
+    let edits = fix_all_edits(diagnostics.iter().cloned());
+    (!edits.is_empty()).then(|| {
+        CodeActionOrCommand::CodeAction(CodeAction {
+            title: "Fix all misspellings".to_owned(),
+            kind: Some(CodeActionKind::SOURCE_FIX_ALL),
+            edit: Some(lsp_types::WorkspaceEdit {
+                changes: Some([(uri.clone(), edits)].into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    })
+}
+
+/// Quick fixes and commands offered for `diagnostic` of the document at
+/// `uri`.
+fn diagnostic_actions(
+    uri: &Url,
+    document: Option<&str>,
+    encoding: &lsp_types::PositionEncodingKind,
+    config: &config::Diagnostics,
+    has_workspace: bool,
+    diagnostic: &lsp_types::Diagnostic,
+) -> Vec<CodeActionOrCommand> {
+    let Some(meta) = diagnostic
+        .data
+        .clone()
+        .and_then(|data| serde_json::from_value::<diagnostic::Meta>(data).ok())
+    else {
+        return Vec::new();
+    };
+    // identifiers are only renamed as a whole, see `replace_all_action`
+    let single_fixes = if meta.identifier.is_some() {
+        &[][..]
+    } else {
+        &meta.replacements[..]
+    };
+    let mut actions: Vec<_> = single_fixes
+        .iter()
+        .map(|value| {
+            let title = if value.is_empty() {
+                "remove".to_owned()
+            } else {
+                format!("replace with `{value}`")
+            };
+            quick_fix(title, uri, [diagnostic.range], value, diagnostic)
+        })
+        .collect();
+    actions.extend(document.and_then(|document| {
+        replace_all_action(uri, document, encoding, config, diagnostic, &meta)
+    }));
+    actions.extend(dictionary_actions(&meta, has_workspace));
+    actions.extend(rule_actions(uri, &meta));
+    actions
+}
+
+/// Quick fix for `diagnostic` replacing `ranges` of the document at `uri`
+/// with `new_text`.
+fn quick_fix(
+    title: String,
+    uri: &Url,
+    ranges: impl IntoIterator<Item = lsp_types::Range>,
+    new_text: &str,
+    diagnostic: &lsp_types::Diagnostic,
+) -> CodeActionOrCommand {
+    CodeActionOrCommand::CodeAction(CodeAction {
+        title,
+        kind: Some(CodeActionKind::QUICKFIX),
+        edit: Some(lsp_types::WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Edits(vec![TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier {
+                    uri: uri.clone(),
+                    version: None,
+                },
+                edits: ranges
+                    .into_iter()
+                    .map(|range| {
+                        OneOf::Left(lsp_types::TextEdit {
+                            range,
+                            new_text: new_text.to_owned(),
+                        })
+                    })
+                    .collect(),
+            }])),
+            ..Default::default()
+        }),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        ..Default::default()
+    })
+}
+
+/// Quick fix applying the first replacement to every occurrence of the
+/// misspelled word if there is more than one, or renaming every occurrence of
+/// the identifier containing it.
+fn replace_all_action(
+    uri: &Url,
+    document: &str,
+    encoding: &lsp_types::PositionEncodingKind,
+    config: &config::Diagnostics,
+    diagnostic: &lsp_types::Diagnostic,
+    meta: &diagnostic::Meta,
+) -> Option<CodeActionOrCommand> {
+    let word = meta.missspelled.as_deref()?;
+    let value = meta.replacements.first()?;
+    let ranges = if let Some(identifier) = &meta.identifier {
+        diagnostic::identifier_occurrences(document, encoding, identifier)
+    } else {
+        diagnostic::occurrences(document, encoding, word, config)
+    };
+    if ranges.is_empty() || meta.identifier.is_none() && ranges.len() < 2 {
+        return None;
+    }
+    let title = if let Some(identifier) = &meta.identifier {
+        format!("rename all `{identifier}` to `{value}`")
+    } else if value.is_empty() {
+        format!("remove all `{word}`")
+    } else {
+        format!("replace all `{word}` with `{value}`")
+    };
+    Some(quick_fix(title, uri, ranges, value, diagnostic))
+}
+
+/// Commands adding the misspelled word to the workspace or user dictionary.
+fn dictionary_actions(meta: &diagnostic::Meta, has_workspace: bool) -> Vec<CodeActionOrCommand> {
+    let Some(word) = &meta.missspelled else {
+        return Vec::new();
+    };
+    let mut actions = Vec::new();
+    if has_workspace {
+        actions.push(command_action(
+            format!("Add `{word}` to workspace dictionary"),
+            &WorkspaceCommand::AddToWorkspaceDictionary,
+            vec![argument(word)],
+        ));
+    }
+    actions.push(command_action(
+        format!("Add `{word}` to dictionary"),
+        &WorkspaceCommand::AddToDictionary,
+        [Some(word), meta.language.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(argument)
+            .collect(),
+    ));
+    actions
+}
+
+/// Commands dismissing the diagnostic or disabling its rule or category.
+fn rule_actions(uri: &Url, meta: &diagnostic::Meta) -> Vec<CodeActionOrCommand> {
+    let mut actions = Vec::new();
+    if let Some(false_positive) = &meta.false_positive {
+        actions.push(command_action(
+            "Ignore this occurrence".to_owned(),
+            &WorkspaceCommand::DismissFalsePositive,
+            vec![argument(false_positive)],
+        ));
+    }
+    if let Some(rule) = &meta.rule {
+        actions.push(command_action(
+            format!("Disable `{rule}`."),
+            &WorkspaceCommand::DisableRule,
+            vec![argument(rule)],
+        ));
+        actions.push(command_action(
+            format!("Disable `{rule}` in this document."),
+            &WorkspaceCommand::DisableRuleInDocument,
+            vec![argument(rule), argument(uri)],
+        ));
+    }
+    if let Some(category) = &meta.category {
+        actions.push(command_action(
+            format!("Disable category `{}`.", category.name),
+            &WorkspaceCommand::DisableCategory,
+            vec![argument(&category.id)],
+        ));
+    }
+    actions
+}
+
+/// Code action running the workspace `command` with `arguments`.
+fn command_action(
+    title: String,
+    command: &WorkspaceCommand,
+    arguments: Vec<Value>,
+) -> CodeActionOrCommand {
+    CodeActionOrCommand::Command(lsp_types::Command {
+        title,
+        command: command.to_string(),
+        arguments: Some(arguments),
+    })
+}
+
+/// Argument of a [`command_action`].
+fn argument(value: impl Serialize) -> Value {
+    serde_json::to_value(value).expect("arguments can be serialized")
+}
+
+/// Checks taking longer than this report their progress.
+const PROGRESS_DELAY: Duration = Duration::from_millis(500);
+
+/// Dictionary words shorter than this are not worth completing.
+const MIN_COMPLETION_LEN: usize = 6;
+
+/// Dictionary words completing the word before the cursor in `line`.
+fn word_completions(line: &str, state: &State) -> Vec<lsp_types::CompletionItem> {
+    let word = line
+        .rsplit(|c: char| !c.is_alphanumeric())
+        .next()
+        .unwrap_or_default();
+    if word.is_empty() {
+        return Vec::new();
+    }
+    let words: BTreeSet<_> = state
+        .dictionary
+        .values()
+        .flatten()
+        .chain(&state.external_dictionary)
+        .chain(&state.workspace_dictionary)
+        .filter(|candidate| {
+            candidate.chars().count() >= MIN_COMPLETION_LEN
+                && candidate.starts_with(word)
+                && *candidate != word
+        })
+        .collect();
+    words
+        .into_iter()
+        .map(|word| lsp_types::CompletionItem {
+            label: word.clone(),
+            kind: Some(lsp_types::CompletionItemKind::TEXT),
+            detail: Some("dictionary".to_owned()),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Options of the directive before the cursor, i.e., `directive` is the text
+/// after [`DIRECTIVE_PREFIX`].
+fn directive_completions(
+    directive: &str,
+    state: &State,
+    rules: &BTreeSet<String>,
+) -> Vec<lsp_types::CompletionItem> {
+    let item = |label: &str, kind| lsp_types::CompletionItem {
+        label: label.to_owned(),
+        kind: Some(kind),
+        ..Default::default()
+    };
+    let current = directive
+        .rsplit(char::is_whitespace)
+        .next()
+        .unwrap_or_default();
+    if current.starts_with("language=") {
+        let languages: BTreeSet<_> = state
+            .dictionary
+            .keys()
+            .map(String::as_str)
+            .chain([state.language.as_str(), DEFAULT_LANGUAGE, AUTO_LANGUAGE])
+            .collect();
+        return languages
+            .into_iter()
+            .map(|language| item(language, lsp_types::CompletionItemKind::VALUE))
+            .collect();
+    }
+    let command = directive.split_whitespace().next().unwrap_or_default();
+    // rule ids follow the command
+    if command.starts_with("disable") && current != command {
+        return rules
+            .iter()
+            .map(|rule| item(rule, lsp_types::CompletionItemKind::ENUM_MEMBER))
+            .collect();
+    }
+    ["disable", "disable-next-line", "enable", "language="]
+        .into_iter()
+        .map(|option| item(option, lsp_types::CompletionItemKind::KEYWORD))
+        .collect()
+}
+
+/// Asks the client to pull diagnostics again, e.g., after a word was added to
+/// the dictionary.
+fn refresh_diagnostics(client: &Client) {
+    let client = client.clone();
+    tokio::spawn(async move {
+        if let Err(e) = client.send_request::<WorkspaceDiagnosticRefresh>(()).await {
+            warn!("unable to refresh diagnostics: {}", e.message);
+        }
+    });
+}
+
+/// Diagnostics of one rule in a document from which on disabling the rule is
+/// suggested.
+const NOISY_RULE_DIAGNOSTICS: usize = 10;
+
+/// Rules, other than misspellings, with at least [`NOISY_RULE_DIAGNOSTICS`]
+/// in `diagnostics` and their count, skipping and adding to `suggested`.
+fn noisy_rules(
+    diagnostics: &[lsp_types::Diagnostic],
+    suggested: &mut HashSet<String>,
+) -> Vec<(String, usize)> {
+    let mut counts = BTreeMap::<String, usize>::new();
+    for diagnostic in diagnostics {
+        let Some(meta) = diagnostic
+            .data
+            .clone()
+            .and_then(|data| serde_json::from_value::<diagnostic::Meta>(data).ok())
+        else {
+            continue;
+        };
+        if let (None, Some(rule)) = (meta.missspelled, meta.rule) {
+            *counts.entry(rule).or_default() += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .filter(|(rule, count)| *count >= NOISY_RULE_DIAGNOSTICS && suggested.insert(rule.clone()))
+        .collect()
+}
+
+/// Offers to disable each [noisy rule](noisy_rules) of the document `name`,
+/// at most once per rule, calls `on_disable` after disabling one.
+fn suggest_disabling_noisy_rules(
+    client: &Client,
+    name: &str,
+    diagnostics: &[lsp_types::Diagnostic],
+    suggested: &std::sync::Mutex<HashSet<String>>,
+    state: Weak<watch::Sender<State>>,
+    on_disable: impl Fn() + Clone + Send + 'static,
+) {
+    let rules = noisy_rules(
+        diagnostics,
+        &mut suggested
+            .lock()
+            .expect("suggested lock should not be poisoned"),
+    );
+    for (rule, count) in rules {
+        let client = client.clone();
+        let message = format!("`{rule}` reported {count} problems in `{name}`.");
+        let state = state.clone();
+        let on_disable = on_disable.clone();
+        // the user might never answer, so the check must not wait for it
+        tokio::spawn(async move {
+            let disable = lsp_types::MessageActionItem {
+                title: format!("Disable `{rule}`"),
+                properties: HashMap::new(),
+            };
+            let keep = lsp_types::MessageActionItem {
+                title: "Keep".to_owned(),
+                properties: HashMap::new(),
+            };
+            let action = match client
+                .show_message_request(MessageType::INFO, message, vec![disable.clone(), keep])
+                .await
+            {
+                Ok(action) => action,
+                Err(e) => {
+                    warn!("unable to suggest disabling `{rule}`: {}", e.message);
+                    return;
+                }
+            };
+            if action.is_some_and(|action| action.title == disable.title) {
+                if let Some(state) = state.upgrade() {
+                    if state.send_if_modified(|state| state.disabled_rules.insert(rule)) {
+                        on_disable();
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Documents waiting to be diagnosed.
+#[derive(Default)]
+struct Queue {
+    documents: HashSet<Url>,
+    /// Changed documents with the time they are due to be diagnosed.
+    delayed: HashMap<Url, Instant>,
+    /// Document last reported via [`Focus`] or edited, checked before any
+    /// other.
+    focused: Option<Url>,
+}
+
+impl Queue {
+    fn pop(&mut self) -> Option<Url> {
+        let now = Instant::now();
+        self.delayed.retain(|uri, due| {
+            let pending = *due > now;
+            if !pending {
+                self.documents.insert(uri.clone());
+            }
+            pending
+        });
+        if let Some(uri) = self
+            .focused
+            .as_ref()
+            .and_then(|focused| self.documents.take(focused))
+        {
+            return Some(uri);
+        }
+        let uri = self.documents.iter().next()?.clone();
+        self.documents.take(&uri)
+    }
+
+    /// When the next delayed document is due.
+    fn next_due(&self) -> Option<Instant> {
+        self.delayed.values().min().copied()
+    }
+}
+
+/// Root of the workspace, multi-root workspaces share the state of the first
+/// folder.
+#[allow(deprecated)]
+fn workspace_root(params: &lsp_types::InitializeParams) -> Option<PathBuf> {
+    params
+        .workspace_folders
+        .as_ref()
+        .and_then(|folders| folders.first())
+        .map(|folder| &folder.uri)
+        .or(params.root_uri.as_ref())
+        .and_then(|uri| uri.to_file_path().ok())
+}
+
+/// Starts the configured server, supervised and reporting its readiness to
+/// `client`, and a checker using it.
+async fn start_checker(
+    config: &config::Config,
+    client: &Client,
+) -> Result<(Arc<std::sync::Mutex<Option<ServerProcess>>>, Arc<Checker>)> {
+    let (ltex_server, checker) = start_server(config, Some(client)).await?;
+    let checker = Arc::new(checker);
+    if config.diagnostics.persistent_cache {
+        if let Some(cache_location) = cache_location() {
+            checker.load_cache(&cache_location);
+        }
+    }
+    report_readiness(client, &checker, ltex_server.is_some());
+    Ok((supervise(ltex_server, client.clone()), checker))
+}
+
+/// Reports to `client` once the LanguageTool server is ready, or why it is
+/// not, as progress if the server is `starting`.
+fn report_readiness(client: &Client, checker: &Arc<Checker>, starting: bool) {
+    let client = client.clone();
+    let checker = checker.clone();
+    tokio::spawn(async move {
+        let progress = if starting {
+            client.begin_progress("Starting LanguageTool server…").await
+        } else {
+            None
+        };
+        let message = checker
+            .wait_until_ready()
+            .await
+            .err()
+            .map(|e| e.to_string());
+        if let Some(progress) = progress {
+            client.end_progress(progress, message.clone());
+        }
+        if let Some(message) = message {
+            client.show_message(MessageType::ERROR, message);
+        }
+    });
+}
+
+/// State read from `state_location` with the configured and workspace
+/// dictionaries, `changes` are written to `state_location` from then on by the
+/// returned writer.
+fn initial_state(
+    config: &config::Config,
+    state_location: Option<&Path>,
+    workspace_dictionary: Option<&Path>,
+    changes: watch::Receiver<State>,
+    client: &Client,
+) -> Result<(State, Option<JoinHandle<()>>)> {
+    let (mut state, writer) = if let Some(state_location) = state_location {
+        let (state, writer) = state::update(changes, state_location)?;
+        (state, Some(writer))
+    } else {
+        client.show_message(
+            MessageType::WARNING,
+            "unable to find home directory, dictionary and disabled rules will not be persisted; \
+             configure `state.location` to fix this",
+        );
+        (State::default(), None)
+    };
+    state.external_dictionary = state::load_dictionaries(&config.state.dictionary_files);
+    if let Some(workspace_dictionary) = workspace_dictionary {
+        state.workspace_dictionary = state::load_workspace_dictionary(workspace_dictionary);
+    }
+    Ok((state, writer))
+}
+
+/// Checks all documents again, e.g., after a reload of the state or config.
+fn rediagnose_all(
+    documents: Arc<Mutex<HashMap<Url, Document>>>,
+    queue: Weak<watch::Sender<Queue>>,
+    client: Client,
+    revision: Arc<AtomicU64>,
+    pull_diagnostics: bool,
+) -> impl Fn() + Clone + Send + Sync + 'static {
+    move || {
+        revision.fetch_add(1, Ordering::Relaxed);
+        if pull_diagnostics {
+            refresh_diagnostics(&client);
+            return;
+        }
+        let documents = documents.clone();
+        let queue = queue.clone();
+        tokio::spawn(async move {
+            let documents = documents.lock().await;
+            if let Some(queue) = queue.upgrade() {
+                queue.send_modify(|queue| {
+                    queue.documents.extend(documents.keys().cloned());
+                });
+            }
+        });
+    }
+}
+
+/// Checks the documents of the [`Queue`] one at a time and publishes their
+/// diagnostics.
+struct DiagnoseWorker<R> {
+    client: Client,
+    checker: Arc<Checker>,
+    documents: Arc<Mutex<HashMap<Url, Document>>>,
+    diagnostics: Arc<Mutex<HashMap<Url, Vec<lsp_types::Diagnostic>>>>,
+    queue: Weak<watch::Sender<Queue>>,
+    config: watch::Receiver<config::Config>,
+    state: watch::Receiver<State>,
+    /// To disable [noisy rules](noisy_rules).
+    state_sender: Weak<watch::Sender<State>>,
+    noisy_rules: Arc<std::sync::Mutex<HashSet<String>>>,
+    /// Called after disabling a noisy rule.
+    rediagnose: R,
+    /// Whether a failure was shown, to only interrupt the user once, e.g., for
+    /// an unreachable server.
+    reported_failure: bool,
+    /// Whether exceeding the rate limit was shown, separate from other
+    /// failures as the checks continue on their own.
+    reported_rate_limit: bool,
+}
+
+impl<R: Fn() + Clone + Send + Sync + 'static> DiagnoseWorker<R> {
+    /// Diagnoses queued documents until the queue is dropped.
+    async fn run(mut self, mut changes: watch::Receiver<Queue>) {
+        let mut document = Document::default();
+        loop {
+            // take one document at a time, so a focus change applies immediately
+            let mut next_due = None;
+            let Some(uri) = self.queue.upgrade().and_then(|queue| {
+                let mut next = None;
+                queue.send_if_modified(|queue| {
+                    next = queue.pop();
+                    next_due = queue.next_due();
+                    false
+                });
+                next
+            }) else {
+                let changed = match next_due {
+                    Some(due) => timeout_at(due, changes.changed()).await.unwrap_or(Ok(())),
+                    None => changes.changed().await,
+                };
+                if changed.is_err() {
+                    break;
+                }
+                continue;
+            };
+            info!("diagnosing {uri}");
+            let documents = self.documents.lock().await;
+            let Some(current) = documents.get(&uri) else {
+                continue;
+            };
+            current.clone_into(&mut document);
+            drop(documents);
+            self.diagnose(uri, &document).await;
+        }
+    }
+
+    async fn diagnose(&mut self, uri: Url, document: &Document) {
+        let (state, diagnostics_config) = {
+            let config = self.config.borrow();
+            let state = self
+                .state
+                .borrow()
+                .for_document(&config, &uri, &document.language_id);
+            (state, config.diagnostics.clone())
+        };
+        let name = uri
+            .path_segments()
+            .and_then(Iterator::last)
+            .unwrap_or_default();
+        let result = diagnose_reporting_progress(
+            &self.client,
+            name,
+            document,
+            &self.checker,
+            &state,
+            &diagnostics_config,
+        )
+        .await;
+        match result {
+            Err(e) => {
+                error!("{e:?}");
+                if let Some(limited) = e.downcast_ref::<RateLimited>() {
+                    if let Some(queue) = self.queue.upgrade() {
+                        queue.send_modify(|queue| {
+                            queue.delayed.insert(uri, limited.until);
+                        });
+                    }
+                    if self.reported_rate_limit {
+                        self.client
+                            .log_message(MessageType::WARNING, limited.to_string());
+                    } else {
+                        self.reported_rate_limit = true;
+                        self.client
+                            .show_message(MessageType::WARNING, limited.to_string());
+                    }
+                    return;
+                }
+                let message = format!("unable to check `{uri}`: {e:?}");
+                if self.reported_failure {
+                    self.client.log_message(MessageType::ERROR, message);
+                } else {
+                    self.reported_failure = true;
+                    self.client.show_message(MessageType::ERROR, message);
+                }
+            }
+            Ok(diagnostics) => {
+                self.reported_failure = false;
+                self.reported_rate_limit = false;
+                suggest_disabling_noisy_rules(
+                    &self.client,
+                    name,
+                    &diagnostics,
+                    &self.noisy_rules,
+                    self.state_sender.clone(),
+                    self.rediagnose.clone(),
+                );
+                self.diagnostics
+                    .lock()
+                    .await
+                    .insert(uri.clone(), diagnostics.clone());
+                self.client.publish_diagnostics(uri, diagnostics);
+            }
+        }
+    }
+}
+
+/// Diagnoses `document`, reporting the progress to `client` once the check
+/// takes longer than [`PROGRESS_DELAY`].
+async fn diagnose_reporting_progress(
+    client: &Client,
+    name: &str,
+    document: &Document,
+    checker: &Checker,
+    state: &State,
+    config: &config::Diagnostics,
+) -> anyhow::Result<Vec<lsp_types::Diagnostic>> {
+    let progress = OnceLock::new();
+    let check = diagnose_with_progress(
+        &document.text,
+        &document.language_id,
+        client.position_encoding(),
+        checker,
+        state,
+        config,
+        |checked, total| {
+            if let Some(progress) = progress.get() {
+                client.send_progress(
+                    progress.clone(),
+                    lsp_types::WorkDoneProgress::Report(lsp_types::WorkDoneProgressReport {
+                        message: Some(format!("{checked}/{total} comments")),
+                        percentage: u32::try_from(checked * 100 / total).ok(),
+                        ..Default::default()
+                    }),
+                );
+            }
+        },
+    );
+    // rechecks on every change would flash a progress
+    let begin_progress = async {
+        sleep(PROGRESS_DELAY).await;
+        if let Some(token) = client.begin_progress(format!("Checking {name}")).await {
+            _ = progress.set(token);
+        }
+        future::pending::<()>().await
+    };
+    let result = tokio::select! {
+        result = check => result,
+        () = begin_progress => unreachable!("progress is never done"),
+    };
+    if let Some(progress) = progress.into_inner() {
+        client.end_progress(progress, None);
+    }
+    result
+}
+
+/// Custom notification clients can send when a document gains focus, to have
+/// it checked before other pending documents.
+///
+/// Method: `doc-spelling-lsp/focus`, params: `{ "uri": <document uri> }`.
+enum Focus {}
+
+impl Notification for Focus {
+    type Params = FocusParams;
+
+    const METHOD: &'static str = "doc-spelling-lsp/focus";
+}
+
+#[derive(Serialize, Deserialize)]
+struct FocusParams {
+    uri: Url,
+}
+
+/// Public LanguageTool api used by [`config::Server::Online`] by default.
+const PUBLIC_API_URL: &str = "https://api.languagetool.org";
+/// Rate limit of the public api for free accounts.
+const PUBLIC_API_REQUESTS_PER_MINUTE: u32 = 20;
+
+/// Starts or connects to the configured LanguageTool server, reporting the
+/// extraction of the embedded server as progress to `client`.
+async fn start_server(
+    config: &config::Config,
+    client: Option<&Client>,
+) -> Result<(Option<ServerProcess>, Checker)> {
+    let (credentials, throttle) = match &config.server {
+        config::Server::Online {
+            base_url,
+            credentials,
+            requests_per_minute,
+            ..
+        } => (
+            credentials.clone(),
+            requests_per_minute
+                .or((base_url.is_none() && credentials.is_none())
+                    .then_some(PUBLIC_API_REQUESTS_PER_MINUTE))
+                .map(|requests| Duration::from_secs(60) / requests),
+        ),
+        _ => (None, None),
+    };
+    let (ltex_server, ltex_client) = match config.server.clone() {
+        config::Server::Embedded {
+            location,
+            java_executable,
+            jvm_args,
+            config,
+        } => {
+            let java = show_error(client, java(java_executable))?;
+            let base_dirs = directories::BaseDirs::new();
+            let location = &server_location(
+                location,
+                base_dirs.as_ref().map(directories::BaseDirs::data_dir),
+                client,
+            );
+            let progress = match client {
+                Some(client) => client.begin_progress("Extracting LanguageTool…").await,
+                None => None,
+            };
+            let server_executable = embedded_language_tool::extract(location);
+            if let (Some(client), Some(progress)) = (client, progress) {
+                client.end_progress(progress, None);
+            }
+            let server_executable = match server_executable {
+                Ok(o) => o,
+                Err(e) => return Err(internal_error!("{e}")),
+            };
+            let mut command = Command::new(java);
+            command
+                .args(jvm_args)
+                .arg("-cp")
+                .arg(&server_executable)
+                .arg("org.languagetool.server.HTTPServer");
+            run_server(command, config)?
+        }
+        config::Server::Online { base_url, port, .. } => {
+            let base_url = base_url.unwrap_or_else(|| PUBLIC_API_URL.to_owned());
+            (
+                None,
+                ServerClient::new(&base_url, &port.map(|p| p.to_string()).unwrap_or_default()),
+            )
+        }
+        config::Server::Local { executable, config }
+            if Path::new(&executable)
+                .extension()
+                .is_some_and(|extension| extension == "jar") =>
+        {
+            let java = show_error(client, java(None))?;
+            let mut command = Command::new(java);
+            command
+                .arg("-cp")
+                .arg(executable)
+                .arg("org.languagetool.server.HTTPServer");
+            run_server(command, config)?
+        }
+        config::Server::Local { executable, config } => {
+            run_server(Command::new(executable), config)?
+        }
+    };
+
+    Ok((
+        ltex_server,
+        Checker::new(
+            ltex_client,
+            credentials,
+            config.diagnostics.cache_size,
+            config.diagnostics.max_concurrent_checks,
+            throttle,
+        ),
+    ))
+}
+
+/// Directory to extract the embedded server to, i.e., the `configured` one or
+/// `language` in the `data_dir` of the user.
+///
+/// Without home directory, i.e., `data_dir`, falls back to the temporary
+/// directory and reports that to `client`.
+fn server_location(
+    configured: Option<PathBuf>,
+    data_dir: Option<&Path>,
+    client: Option<&Client>,
+) -> PathBuf {
+    if let Some(location) = configured {
+        return location;
+    }
+    if let Some(data_dir) = data_dir {
+        return data_dir.join("language");
+    }
+    let location = env::temp_dir().join("doc-spelling-lsp");
+    let message = format!(
+        "unable to find home directory, extracting LanguageTool to `{}`; configure \
+         `server.location` to fix this",
+        location.display()
+    );
+    warn!("{message}");
+    if let Some(client) = client {
+        client.log_message(MessageType::WARNING, message);
+    }
+    location
+}
+
+/// Also shows an error to the user, as errors during initialization are only
+/// logged.
+fn show_error<T>(client: Option<&Client>, result: Result<T>) -> Result<T> {
+    if let (Some(client), Err(e)) = (client, &result) {
+        client.show_message(MessageType::ERROR, e.message.clone());
+    }
+    result
+}
+
+/// Oldest Java version the embedded LanguageTool server runs on.
+const MIN_JAVA_VERSION: u32 = 17;
+
+/// Resolves the Java executable, i.e., the configured one,
+/// `$JAVA_HOME/bin/java` or `java`, and ensures it is recent enough.
+fn java(configured: Option<PathBuf>) -> Result<PathBuf> {
+    let java = configured
+        .or_else(|| {
+            env::var_os("JAVA_HOME").map(|home| PathBuf::from(home).join("bin").join("java"))
+        })
+        .unwrap_or_else(|| "java".into());
+    let output = match Command::new(&java).arg("-version").output() {
+        Ok(output) => output,
+        Err(e) => {
+            return Err(request_failed!(
+                "unable to run Java `{}`: {e}\nthe embedded LanguageTool server needs Java \
+                 {MIN_JAVA_VERSION} or newer, install it or configure `server.java_executable`",
+                java.display()
+            ));
+        }
+    };
+    let version = String::from_utf8_lossy(&output.stderr);
+    let major = java_major_version(&version).request_failed(format!(
+        "unable to determine version of Java `{}` from:\n{}",
+        java.display(),
+        version.trim()
+    ))?;
+    if major < MIN_JAVA_VERSION {
+        return Err(request_failed!(
+            "Java `{}` is version {major}, the embedded LanguageTool server needs Java \
+             {MIN_JAVA_VERSION} or newer, install it or configure `server.java_executable`",
+            java.display()
+        ));
+    }
+    Ok(java)
+}
+
+/// Parses the major version printed by `java -version`, e.g.,
+/// `openjdk version "17.0.2"` or `java version "1.8.0_292"`.
+fn java_major_version(output: &str) -> Option<u32> {
+    let (_, version) = output.split_once('"')?;
+    let (version, _) = version.split_once('"')?;
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+    match parts.next()?.parse().ok()? {
+        1 => parts.next()?.parse().ok(),
+        major => Some(major),
     }
 }
 
 fn run_server(
-    command: &mut Command,
-    config::LocalServer { port, extra_args }: config::LocalServer,
-) -> Result<(Option<Child>, ServerClient)> {
+    mut command: Command,
+    config::LocalServer {
+        port,
+        server_args,
+        env,
+        ngram_dir,
+        word2vec_dir,
+        shared,
+    }: config::LocalServer,
+) -> Result<(Option<ServerProcess>, ServerClient)> {
+    let program = command.get_program().to_string_lossy().to_string();
+    command.envs(env);
+    if let Some(ngram_dir) = ngram_dir {
+        command.arg("--languageModel").arg(ngram_dir);
+    }
+    if let Some(word2vec_dir) = word2vec_dir {
+        command.arg("--word2vecModel").arg(word2vec_dir);
+    }
+    command.args(server_args);
+    // held until the spawned server is registered
+    let lock = if shared {
+        shared::Lock::acquire()
+            .map_err(|e| warn!("unable to share server, spawning a separate one: {e}"))
+            .ok()
+    } else {
+        None
+    };
+    let command_line = shared::command_line(&command);
+    if let Some(port) = lock.as_ref().and_then(|lock| lock.connect(&command_line)) {
+        info!("connecting to shared server on port {port}");
+        return Ok((
+            Some(ServerProcess {
+                child: None,
+                command,
+                shared: true,
+            }),
+            languagetool_rust::ServerClient::new("http://localhost", &port.to_string()),
+        ));
+    }
     let port = port
         .or_else(portpicker::pick_unused_port)
-        .internal_error("unable to find unused port")?
-        .to_string();
-    let program = command.get_program().to_string_lossy().to_string();
+        .internal_error("unable to find unused port")?;
+    let child = match command.arg("--port").arg(port.to_string()).spawn() {
+        Ok(child) => child,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(request_failed!(
+                "unable to find language tool server executable `{program}`"
+            ));
+        }
+        Err(e) => {
+            return Err(internal_error!(
+                "spawning language tool server `{program}`:\n{e}"
+            ));
+        }
+    };
+    let shared = match lock.map(|lock| lock.register(command_line, port, child.id())) {
+        Some(Ok(())) => true,
+        Some(Err(e)) => {
+            warn!("unable to share server: {e}");
+            false
+        }
+        None => false,
+    };
     Ok((
-        Some(
-            command
-                .arg("--port")
-                .arg(&port)
-                .args(extra_args)
-                .spawn()
-                .internal_error(format!("spawning language tool server `{program}`"))?,
-        ),
-        languagetool_rust::ServerClient::new("http://localhost", &port),
+        Some(ServerProcess {
+            child: Some(child),
+            command,
+            shared,
+        }),
+        languagetool_rust::ServerClient::new("http://localhost", &port.to_string()),
     ))
 }
 
+/// LanguageTool server spawned by us or, if shared, by another instance.
+struct ServerProcess {
+    /// `None` if spawned by another instance.
+    child: Option<Child>,
+    /// Command the server was spawned with, to restart it on the same port.
+    command: Command,
+    /// Whether the server is registered to be shared, see [`shared`].
+    shared: bool,
+}
+
+impl ServerProcess {
+    /// Kills the server, unless it is shared and still used by other
+    /// instances.
+    fn kill(&mut self) -> io::Result<()> {
+        if !self.shared {
+            return self.child.as_mut().map_or(Ok(()), Child::kill);
+        }
+        let Some(pid) = shared::Lock::acquire()?.release()? else {
+            return Ok(());
+        };
+        match &mut self.child {
+            Some(child) if child.id() == pid => child.kill(),
+            _ => shared::kill(pid),
+        }
+    }
+}
+
+/// Interval to check whether the spawned server is still running.
+const SERVER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Longest delay before restarting a server that keeps exiting.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+/// Restarts the server whenever it exits, with increasing delays if it exits
+/// again shortly after, until it is taken out of the returned mutex or that is
+/// dropped.
+///
+/// Shared servers are only restarted by the instance that spawned them.
+fn supervise(
+    server: Option<ServerProcess>,
+    client: Client,
+) -> Arc<std::sync::Mutex<Option<ServerProcess>>> {
+    let server = Arc::new(std::sync::Mutex::new(server));
+    let supervised = Arc::downgrade(&server);
+    tokio::spawn(async move {
+        let mut delay = SERVER_CHECK_INTERVAL;
+        let mut started = Instant::now();
+        loop {
+            sleep(SERVER_CHECK_INTERVAL).await;
+            let Some(server) = supervised.upgrade() else {
+                break;
+            };
+            let exited = {
+                let mut server = server.lock().expect("server lock should not be poisoned");
+                let Some(child) = server.as_mut().and_then(|process| process.child.as_mut()) else {
+                    break;
+                };
+                match child.try_wait() {
+                    Ok(None) => continue,
+                    Ok(Some(status)) => status.to_string(),
+                    Err(e) => e.to_string(),
+                }
+            };
+            // only back off if the server keeps exiting
+            if started.elapsed() > MAX_RESTART_DELAY {
+                delay = SERVER_CHECK_INTERVAL;
+            }
+            warn!("LanguageTool server exited ({exited}), restarting in {delay:?}");
+            client.show_message(
+                MessageType::WARNING,
+                format!(
+                    "LanguageTool server exited ({exited}), restarting in {}s",
+                    delay.as_secs()
+                ),
+            );
+            sleep(delay).await;
+            delay = (delay * 2).min(MAX_RESTART_DELAY);
+            started = Instant::now();
+            let mut server = server.lock().expect("server lock should not be poisoned");
+            let Some(process) = server.as_mut() else {
+                break;
+            };
+            match process.command.spawn() {
+                Ok(child) => {
+                    if process.shared {
+                        if let Err(e) =
+                            shared::Lock::acquire().and_then(|lock| lock.restarted(child.id()))
+                        {
+                            warn!("unable to update shared server: {e}");
+                        }
+                    }
+                    process.child = Some(child);
+                }
+                Err(e) => {
+                    error!("unable to restart LanguageTool server: {e}");
+                    client.show_message(
+                        MessageType::ERROR,
+                        format!("unable to restart LanguageTool server: {e}"),
+                    );
+                }
+            }
+        }
+    });
+    server
+}
+
 #[derive(Display, FromStr)]
 enum WorkspaceCommand {
+    /// Expects the word and optionally the language, defaulting to the
+    /// current language.
     AddToDictionary,
+    /// Reverts [`WorkspaceCommand::AddToDictionary`], expects the word and
+    /// optionally the language, defaulting to all languages.
+    RemoveFromDictionary,
+    /// Appends the word to `.doc-spelling/dictionary.txt` in the workspace
+    /// root, to share it with everyone working on the project.
+    AddToWorkspaceDictionary,
     DisableRule,
+    /// Like [`WorkspaceCommand::DisableRule`] for a single document, expects
+    /// the rule id and the document `Url`.
+    DisableRuleInDocument,
+    /// Reverts [`WorkspaceCommand::DisableRule`], expects the rule id.
+    EnableRule,
+    /// Returns the ids of all disabled rules.
+    ListDisabledRules,
+    DisableCategory,
+    /// Checks only the comments overlapping a range, expects the document
+    /// `Url` and the `Range` as arguments and returns the diagnostics.
+    CheckRange,
+    /// Clears the dictionary, dismissed false positives and all disabled rules
+    /// and categories and resets the language, expects `true` as argument to
+    /// confirm.
+    ResetState,
+    /// Sets the language documents are checked in, e.g., `de-DE`, or `auto`
+    /// to detect it, expects the language and optionally the document `Url`
+    /// to only set it for that document.
+    SetLanguage,
+    /// Ignores a single occurrence, expects the
+    /// [`FalsePositive`](state::FalsePositive) from the diagnostic data.
+    DismissFalsePositive,
+    /// Applies the first replacement of every misspelling in a document,
+    /// expects the document `Url` as argument.
+    FixAll,
 }
 
 impl WorkspaceCommand {
     fn options() -> Vec<String> {
-        vec![Self::AddToDictionary.to_string()]
+        vec![
+            Self::AddToDictionary.to_string(),
+            Self::RemoveFromDictionary.to_string(),
+            Self::AddToWorkspaceDictionary.to_string(),
+            Self::DisableRule.to_string(),
+            Self::DisableRuleInDocument.to_string(),
+            Self::EnableRule.to_string(),
+            Self::ListDisabledRules.to_string(),
+            Self::DisableCategory.to_string(),
+            Self::CheckRange.to_string(),
+            Self::ResetState.to_string(),
+            Self::SetLanguage.to_string(),
+            Self::DismissFalsePositive.to_string(),
+            Self::FixAll.to_string(),
+        ]
+    }
+}
+
+/// Arguments of a [`WorkspaceCommand`], taken in order.
+struct Arguments {
+    command: String,
+    values: std::vec::IntoIter<Value>,
+}
+
+impl Arguments {
+    fn new(command: &WorkspaceCommand, values: Vec<Value>) -> Self {
+        Self {
+            command: command.to_string(),
+            values: values.into_iter(),
+        }
+    }
+
+    /// Next argument, described as `name` in errors.
+    fn required<T: DeserializeOwned>(&mut self, name: &str) -> Result<T> {
+        let value = self
+            .values
+            .next()
+            .invalid_params(format!("{} requires {name} argument", self.command))?;
+        serde_json::from_value(value)
+            .invalid_params(format!("{} expects {name} argument", self.command))
+    }
+
+    /// Next argument, `None` if it is missing or `null`.
+    fn optional<T: DeserializeOwned>(&mut self, name: &str) -> Result<Option<T>> {
+        let Some(value) = self.values.next() else {
+            return Ok(None);
+        };
+        serde_json::from_value(value)
+            .invalid_params(format!("{} expects {name} argument", self.command))
+    }
+}
+
+/// Handlers of the [`WorkspaceCommand`]s.
+impl Lsp {
+    /// Applies `modify` to the state and checks all documents again if it
+    /// changed anything.
+    async fn modify_state(&self, modify: impl FnOnce(&mut State) -> bool) {
+        if self.state.send_if_modified(modify) {
+            self.publish_all_diagnostics().await;
+        }
+    }
+
+    async fn add_to_dictionary(&self, mut arguments: Arguments) -> Result<Option<Value>> {
+        let word: String = arguments.required("word")?;
+        let language: Option<String> = arguments.optional("language")?;
+        self.modify_state(|state| {
+            let language = language.unwrap_or_else(|| state.language.clone());
+            state.dictionary.entry(language).or_default().insert(word)
+        })
+        .await;
+        Ok(None)
+    }
+
+    async fn remove_from_dictionary(&self, mut arguments: Arguments) -> Result<Option<Value>> {
+        let word: String = arguments.required("word")?;
+        let language: Option<String> = arguments.optional("language")?;
+        self.modify_state(|state| {
+            let mut removed = false;
+            for (dictionary_language, dictionary) in &mut state.dictionary {
+                if language.is_none() || language.as_ref() == Some(dictionary_language) {
+                    removed |= dictionary.remove(&word);
+                }
+            }
+            removed
+        })
+        .await;
+        Ok(None)
+    }
+
+    async fn add_to_workspace_dictionary(&self, mut arguments: Arguments) -> Result<Option<Value>> {
+        let word: String = arguments.required("word")?;
+        let workspace_dictionary = self
+            .workspace_dictionary
+            .as_ref()
+            .request_failed("no workspace to add the word to")?;
+        state::append_to_dictionary(workspace_dictionary, &word)?;
+        self.modify_state(|state| state.workspace_dictionary.insert(word))
+            .await;
+        Ok(None)
+    }
+
+    async fn dismiss_false_positive(&self, mut arguments: Arguments) -> Result<Option<Value>> {
+        let false_positive: state::FalsePositive = arguments.required("false positive")?;
+        self.modify_state(|state| state.false_positives.insert(false_positive))
+            .await;
+        Ok(None)
+    }
+
+    async fn set_language(&self, mut arguments: Arguments) -> Result<Option<Value>> {
+        let language: String = arguments.required("language")?;
+        let document: Option<Url> = arguments.optional("document url")?;
+        self.modify_state(|state| {
+            if let Some(document) = document {
+                let previous = state.document_languages.insert(document, language.clone());
+                previous != Some(language)
+            } else if state.language == language {
+                false
+            } else {
+                state.language = language;
+                true
+            }
+        })
+        .await;
+        Ok(None)
+    }
+
+    async fn disable_rule(&self, mut arguments: Arguments) -> Result<Option<Value>> {
+        let rule: String = arguments.required("rule")?;
+        self.modify_state(|state| state.disabled_rules.insert(rule))
+            .await;
+        Ok(None)
+    }
+
+    async fn disable_rule_in_document(&self, mut arguments: Arguments) -> Result<Option<Value>> {
+        let rule: String = arguments.required("rule")?;
+        let document: Url = arguments.required("document url")?;
+        self.modify_state(|state| {
+            state
+                .document_disabled_rules
+                .entry(document)
+                .or_default()
+                .insert(rule)
+        })
+        .await;
+        Ok(None)
+    }
+
+    async fn enable_rule(&self, mut arguments: Arguments) -> Result<Option<Value>> {
+        let rule: String = arguments.required("rule")?;
+        self.modify_state(|state| state.disabled_rules.remove(&rule))
+            .await;
+        Ok(None)
+    }
+
+    fn list_disabled_rules(&self) -> Value {
+        serde_json::to_value(&self.state.borrow().disabled_rules).expect("rules can be serialized")
+    }
+
+    async fn disable_category(&self, mut arguments: Arguments) -> Result<Option<Value>> {
+        let category: String = arguments.required("category")?;
+        self.modify_state(|state| state.disabled_categories.insert(category))
+            .await;
+        Ok(None)
+    }
+
+    async fn check_range(&self, mut arguments: Arguments) -> Result<Option<Value>> {
+        let uri: Url = arguments.required("document url")?;
+        let range: lsp_types::Range = arguments.required("range")?;
+        let (document, state, diagnostics_config) = self.checked_document(&uri).await?;
+        let diagnostics = diagnose_range(
+            &document.text,
+            &document.language_id,
+            self.client.position_encoding(),
+            range,
+            &self.checker,
+            &state,
+            &diagnostics_config,
+        )
+        .await
+        .request_failed("unable to check range")?;
+        Ok(Some(
+            serde_json::to_value(diagnostics).expect("diagnostics can be serialized"),
+        ))
+    }
+
+    async fn reset_state(&self, mut arguments: Arguments) -> Result<Option<Value>> {
+        let confirmed: bool = arguments.required("confirmation")?;
+        if !confirmed {
+            return Err(invalid_params!("ResetState was not confirmed"));
+        }
+        self.state
+            .send_modify(|state| state.replace_persisted(State::default()));
+        self.client.show_message(
+            MessageType::INFO,
+            "reset dictionary, false positives, disabled rules, disabled categories and language",
+        );
+        self.publish_all_diagnostics().await;
+        Ok(None)
+    }
+
+    async fn fix_all(&self, mut arguments: Arguments) -> Result<Option<Value>> {
+        let uri: Url = arguments.required("document url")?;
+        let (document, state, diagnostics_config) = self.checked_document(&uri).await?;
+        let diagnostics = diagnose(
+            &document.text,
+            &document.language_id,
+            self.client.position_encoding(),
+            &self.checker,
+            &state,
+            &diagnostics_config,
+        )
+        .await
+        .request_failed("unable to check document")?;
+        let edits = fix_all_edits(diagnostics);
+        if edits.is_empty() {
+            self.client
+                .show_message(MessageType::INFO, format!("nothing to fix in `{uri}`"));
+            return Ok(None);
+        }
+        let response = self
+            .client
+            .apply_edit("Fix all", lsp_types::WorkspaceEdit {
+                changes: Some([(uri, edits)].into()),
+                ..Default::default()
+            })
+            .await?;
+        if !response.applied {
+            return Err(request_failed!(
+                "client did not apply fixes: {}",
+                response
+                    .failure_reason
+                    .as_deref()
+                    .unwrap_or("no reason given")
+            ));
+        }
+        Ok(None)
     }
 }
 
@@ -117,99 +1706,138 @@ impl LanguageServer for Lsp {
         _options: (),
     ) -> Result<Self> {
         info!("initializing");
-        let config: config::Config = params
-            .initialization_options
-            .map(serde_json::from_value)
-            .transpose()
-            .internal_error("error deserializing config:")?
-            .unwrap_or_default();
+        let workspace = workspace_root(&params);
+        let project_config = workspace.as_deref().map(config::project_file);
+        let settings = params.initialization_options;
+        let config = config::load(settings.clone(), project_config.as_deref())
+            .invalid_params("invalid config")?;
+        let settings = Arc::new(std::sync::Mutex::new(settings));
 
-        let (ltex_server, ltex_client) = match config.server {
-            config::Server::Embedded { location, config } => {
-                let location = &if let Some(location) = location.clone() {
-                    location
-                } else {
-                    directories::BaseDirs::new()
-                        .internal_error("unable to find data dir from environment")?
-                        .data_dir()
-                        .join("language")
-                };
-                let server_executable = match embedded_language_tool::extract(location) {
-                    Ok(o) => o,
-                    Err(e) => return Err(internal_error!("{e}")),
-                };
-                run_server(
-                    Command::new("java")
-                        .arg("-cp")
-                        .arg(&server_executable)
-                        .arg("org.languagetool.server.HTTPServer"),
-                    config,
-                )?
-            }
-            config::Server::Online {} => todo!(),
-            config::Server::Local { .. } => todo!(),
-        };
+        let (ltex_server, checker) = start_checker(&config, &client).await?;
 
-        let documents: Arc<Mutex<HashMap<Url, String>>> = Arc::default();
-        let (diagnose_sender, mut diagnose_recv) = watch::channel(HashSet::new());
+        let documents: Arc<Mutex<HashMap<Url, Document>>> = Arc::default();
+        let diagnostics: Arc<Mutex<HashMap<Url, Vec<lsp_types::Diagnostic>>>> = Arc::default();
+        let (diagnose_sender, diagnose_recv) = watch::channel(Queue::default());
+        let diagnose_sender = Arc::new(diagnose_sender);
         let (state_sender, state_recv) = watch::channel(State::default());
-        state_sender
-            .send(state::update(state_recv.clone(), &config.state)?)
-            .unwrap();
+        let state_sender = Arc::new(state_sender);
+        let state_location = state::location(&config.state, workspace.as_deref())?;
+        let workspace_dictionary = workspace.as_deref().map(state::workspace_dictionary);
+        let (state, state_writer) = initial_state(
+            &config,
+            state_location.as_deref(),
+            workspace_dictionary.as_deref(),
+            state_recv.clone(),
+            &client,
+        )?;
+        state_sender.send(state).unwrap();
+        let pull_diagnostics = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .is_some_and(|text_document| text_document.diagnostic.is_some());
+        let revision = Arc::<AtomicU64>::default();
+        let rediagnose = rediagnose_all(
+            documents.clone(),
+            Arc::downgrade(&diagnose_sender),
+            client.clone(),
+            revision.clone(),
+            pull_diagnostics,
+        );
+        if let Some(state_location) = &state_location {
+            state::reload(
+                Arc::downgrade(&state_sender),
+                state_location.clone(),
+                rediagnose.clone(),
+            );
+        }
+        let config_sender = Arc::new(watch::channel(config).0);
+        if let Some(project_config) = &project_config {
+            reload_project_config(
+                Arc::downgrade(&config_sender),
+                Arc::downgrade(&state_sender),
+                settings.clone(),
+                project_config.clone(),
+                client.clone(),
+                rediagnose.clone(),
+            );
+        }
 
-        {
-            let documents = documents.clone();
-            let mut document = String::new();
-            let mut state = state_recv.borrow().clone();
-            let client = client.clone();
-            tokio::spawn(async move {
-                loop {
-                    diagnose_recv
-                        .changed()
-                        .await
-                        .expect("we should not drop the sender");
-                    info!("diagnosing");
-                    let tasks = diagnose_recv.borrow_and_update().clone();
-                    for uri in tasks {
-                        let documents = documents.lock().await;
-                        documents
-                            .get(&uri)
-                            .expect("we should have just inserted it")
-                            .clone_into(&mut document);
-                        state_recv.borrow().clone_into(&mut state);
-                        drop(documents);
-
-                        match diagnose(&document, &ltex_client, &state).await {
-                            Err(e) => error!("{e:?}"),
-                            Ok(diags) => {
-                                client.publish_diagnostics(uri, diags);
-                            }
-                        };
-                    }
-                }
-            });
+        let noisy_rules = Arc::default();
+        let worker = DiagnoseWorker {
+            client: client.clone(),
+            checker: checker.clone(),
+            documents: documents.clone(),
+            diagnostics: diagnostics.clone(),
+            queue: Arc::downgrade(&diagnose_sender),
+            config: config_sender.subscribe(),
+            state: state_recv,
+            state_sender: Arc::downgrade(&state_sender),
+            noisy_rules: Arc::clone(&noisy_rules),
+            rediagnose,
+            reported_failure: false,
+            reported_rate_limit: false,
         };
+        tokio::spawn(worker.run(diagnose_recv));
         info!("done initializing");
         Ok(Self {
             client,
             ltex_server,
+            checker,
             documents,
+            diagnostics,
             state: state_sender,
+            state_writer,
+            workspace_dictionary,
+            config: config_sender,
+            settings,
+            project_config,
             diagnose: diagnose_sender,
+            pull_diagnostics,
+            revision,
+            noisy_rules,
         })
     }
 
     async fn shutdown(self) -> Result<()> {
         info!("shutting down");
-        if let Some(mut ltex_server) = self.ltex_server {
+        let ltex_server = self
+            .ltex_server
+            .lock()
+            .expect("server lock should not be poisoned")
+            .take();
+        if let Some(mut ltex_server) = ltex_server {
             _ = ltex_server.kill();
         }
+        self.checker.log_cache_metrics();
+        if self.config.borrow().diagnostics.persistent_cache {
+            if let Some(cache_location) = cache_location() {
+                if let Err(e) = self.checker.save_cache(&cache_location) {
+                    error!("{e:#}");
+                }
+            }
+        }
+        // closes the channel, so the writer stops after writing the latest change
+        drop(self.state);
+        if let Some(state_writer) = self.state_writer {
+            state_writer
+                .await
+                .internal_error("unable to write the state")?;
+        }
         Ok(())
     }
 
     async fn did_open(&self, params: lsp_types::DidOpenTextDocumentParams) {
+        let language_id = self
+            .config
+            .borrow()
+            .diagnostics
+            .language_id(&params.text_document.uri, params.text_document.language_id);
         let mut documents = self.documents.lock().await;
-        documents.insert(params.text_document.uri.clone(), params.text_document.text);
+        documents.insert(params.text_document.uri.clone(), Document {
+            text: params.text_document.text,
+            language_id,
+        });
         drop(documents);
         self.publish_diagnostics(params.text_document.uri);
     }
@@ -218,15 +1846,200 @@ impl LanguageServer for Lsp {
         self.publish_diagnostics(params.text_document.uri);
     }
 
-    async fn did_change(&self, mut params: lsp_types::DidChangeTextDocumentParams) {
-        // TODO verify this is full document
+    async fn did_close(&self, params: lsp_types::DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
+        let removed = self.documents.lock().await.remove(&uri).is_some();
+        self.diagnostics.lock().await.remove(&uri);
+        if removed {
+            // clear stale diagnostics, the document is not checked anymore
+            self.client.publish_diagnostics(uri, Vec::new());
+        }
+    }
+
+    async fn did_change(&self, params: lsp_types::DidChangeTextDocumentParams) {
         let mut documents = self.documents.lock().await;
-        documents.insert(
-            params.text_document.uri.clone(),
-            params.content_changes.pop().unwrap().text,
-        );
+        let Some(Document { text: document, .. }) = documents.get_mut(&params.text_document.uri)
+        else {
+            warn!(
+                "ignoring change of unknown document `{}`",
+                params.text_document.uri
+            );
+            return;
+        };
+        // changes apply in order, each to the result of the previous one
+        for change in params.content_changes {
+            if let Some(range) = change.range {
+                diagnostic::apply_change(
+                    document,
+                    self.client.position_encoding(),
+                    range,
+                    &change.text,
+                );
+            } else {
+                *document = change.text;
+            }
+        }
         drop(documents);
-        self.publish_diagnostics(params.text_document.uri);
+        self.publish_diagnostics_delayed(params.text_document.uri);
+    }
+
+    async fn did_change_configuration(&self, params: lsp_types::DidChangeConfigurationParams) {
+        if apply_settings(
+            params.settings,
+            &self.settings,
+            self.project_config.as_deref(),
+            &self.config,
+            &self.state,
+            &self.client,
+        ) {
+            self.publish_all_diagnostics().await;
+        }
+    }
+
+    async fn document_diagnostic(
+        &self,
+        params: lsp_types::DocumentDiagnosticParams,
+    ) -> Result<lsp_types::DocumentDiagnosticReportResult> {
+        use lsp_types::*;
+        let uri = params.text_document.uri;
+        // read before checking, so changes during the check are not missed
+        let result_id = self.revision.load(Ordering::Relaxed).to_string();
+        if params.previous_result_id.as_ref() == Some(&result_id) {
+            return Ok(DocumentDiagnosticReportResult::Report(
+                DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
+                    related_documents: None,
+                    unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport {
+                        result_id,
+                    },
+                }),
+            ));
+        }
+        let (document, state, diagnostics_config) = self.checked_document(&uri).await?;
+        let items = diagnose(
+            &document.text,
+            &document.language_id,
+            self.client.position_encoding(),
+            &self.checker,
+            &state,
+            &diagnostics_config,
+        )
+        .await
+        .request_failed("unable to check document")?;
+        let name = uri
+            .path_segments()
+            .and_then(Iterator::last)
+            .unwrap_or_default();
+        suggest_disabling_noisy_rules(
+            &self.client,
+            name,
+            &items,
+            &self.noisy_rules,
+            Arc::downgrade(&self.state),
+            {
+                let client = self.client.clone();
+                let revision = self.revision.clone();
+                move || {
+                    revision.fetch_add(1, Ordering::Relaxed);
+                    refresh_diagnostics(&client);
+                }
+            },
+        );
+        self.diagnostics.lock().await.insert(uri, items.clone());
+        Ok(DocumentDiagnosticReportResult::Report(
+            DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+                related_documents: None,
+                full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                    result_id: Some(result_id),
+                    items,
+                },
+            }),
+        ))
+    }
+
+    async fn hover(&self, params: lsp_types::HoverParams) -> Result<Option<lsp_types::Hover>> {
+        let lsp_types::TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position_params;
+        let diagnostics = self.diagnostics.lock().await;
+        let Some(diagnostic) = diagnostics.get(&text_document.uri).and_then(|diagnostics| {
+            diagnostics.iter().find(|diagnostic| {
+                diagnostic.range.start <= position && position <= diagnostic.range.end
+            })
+        }) else {
+            return Ok(None);
+        };
+        let meta: Option<diagnostic::Meta> = diagnostic
+            .data
+            .clone()
+            .and_then(|data| serde_json::from_value(data).ok());
+        let mut contents = format!("**{}**", diagnostic.message);
+        if let Some(meta) = meta {
+            if let Some(description) = meta.description {
+                contents.push_str(&format!("\n\n{description}"));
+            }
+            let rule = meta.rule.map(|rule| format!("`{rule}`"));
+            let category = meta.category.map(|category| category.name);
+            let details: Vec<_> = rule.into_iter().chain(category).collect();
+            if !details.is_empty() {
+                contents.push_str(&format!("\n\n{}", details.join(", ")));
+            }
+            for url in meta.urls {
+                contents.push_str(&format!("\n\n<{url}>"));
+            }
+        }
+        Ok(Some(lsp_types::Hover {
+            contents: lsp_types::HoverContents::Markup(lsp_types::MarkupContent {
+                kind: lsp_types::MarkupKind::Markdown,
+                value: contents,
+            }),
+            range: Some(diagnostic.range),
+        }))
+    }
+
+    async fn completion(
+        &self,
+        params: lsp_types::CompletionParams,
+    ) -> Result<Option<lsp_types::CompletionResponse>> {
+        let lsp_types::TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position;
+        let document = self
+            .documents
+            .lock()
+            .await
+            .get(&text_document.uri)
+            .map(|document| document.text.clone())
+            .invalid_params(format!("unknown document `{}`", text_document.uri))?;
+        let Some(line) =
+            diagnostic::comment_line_prefix(&document, self.client.position_encoding(), position)
+        else {
+            return Ok(None);
+        };
+        // rules that could be disabled, i.e., those already disabled or matching
+        let rules: BTreeSet<_> = self
+            .diagnostics
+            .lock()
+            .await
+            .get(&text_document.uri)
+            .into_iter()
+            .flatten()
+            .filter_map(|diagnostic| {
+                serde_json::from_value::<diagnostic::Meta>(diagnostic.data.clone()?)
+                    .ok()?
+                    .rule
+            })
+            .chain(self.state.borrow().disabled_rules.iter().cloned())
+            .collect();
+        let state = self.state.borrow();
+        let items = match line.rsplit_once(DIRECTIVE_PREFIX) {
+            Some((_, directive)) if !directive.contains("-->") => {
+                directive_completions(directive, &state, &rules)
+            }
+            _ => word_completions(line, &state),
+        };
+        Ok(Some(lsp_types::CompletionResponse::Array(items)))
     }
 
     async fn code_action(
@@ -235,108 +2048,607 @@ impl LanguageServer for Lsp {
     ) -> Result<Option<Vec<lsp_types::CodeActionOrCommand>>> {
         info!("handling code action {params:?}");
         let uri = params.text_document.uri;
+        let document = self
+            .documents
+            .lock()
+            .await
+            .get(&uri)
+            .map(|document| document.text.clone());
+        let diagnostics_config = self.config.borrow().diagnostics.clone();
+        let encoding = self.client.position_encoding();
+        let has_workspace = self.workspace_dictionary.is_some();
+        let fix_all_requested = match &params.context.only {
+            Some(only) => only.iter().any(|kind| {
+                [CodeActionKind::SOURCE, CodeActionKind::SOURCE_FIX_ALL].contains(kind)
+            }),
+            None => true,
+        };
+        let fix_all = if fix_all_requested {
+            self.diagnostics
+                .lock()
+                .await
+                .get(&uri)
+                .and_then(|diagnostics| fix_all_action(&uri, diagnostics))
+        } else {
+            None
+        };
         Ok(Some(
             params
                 .context
                 .diagnostics
-                .into_iter()
-                .filter_map(move |diagnostic| {
-                    let meta: diagnostic::Meta =
-                        serde_json::from_value(diagnostic.data.as_ref()?.clone()).ok()?;
-                    Some(
-                        meta.replacements
-                            .into_iter()
-                            .map({
-                                let uri = uri.clone();
-                                move |value| {
-                                    CodeActionOrCommand::CodeAction(CodeAction {
-                                        title: format!("replace with `{value}`"),
-                                        kind: Some(CodeActionKind::QUICKFIX),
-                                        edit: Some(lsp_types::WorkspaceEdit {
-                                            changes: None,
-                                            document_changes: Some(DocumentChanges::Edits(vec![
-                                                TextDocumentEdit {
-                                                    text_document:
-                                                        OptionalVersionedTextDocumentIdentifier {
-                                                            uri: uri.clone(),
-                                                            version: None,
-                                                        },
-                                                    edits: vec![OneOf::Left(lsp_types::TextEdit {
-                                                        range: diagnostic.range,
-                                                        new_text: value,
-                                                    })],
-                                                },
-                                            ])),
-                                            ..Default::default()
-                                        }),
-                                        diagnostics: Some(vec![diagnostic.clone()]),
-                                        ..Default::default()
-                                    })
-                                }
-                            })
-                            .chain(meta.missspelled.map(|word| {
-                                lsp_types::CodeActionOrCommand::Command(lsp_types::Command {
-                                    title: format!("Add `{word}` to dictionary"),
-                                    command: WorkspaceCommand::AddToDictionary.to_string(),
-                                    arguments: Some(vec![
-                                        serde_json::to_value(word)
-                                            .expect("string can be serialized"),
-                                    ]),
-                                })
-                            }))
-                            .chain(meta.rule.map(|rule| {
-                                lsp_types::CodeActionOrCommand::Command(lsp_types::Command {
-                                    title: format!("Disable `{rule}`."),
-                                    command: WorkspaceCommand::DisableRule.to_string(),
-                                    arguments: Some(vec![
-                                        serde_json::to_value(rule)
-                                            .expect("string can be serialized"),
-                                    ]),
-                                })
-                            })),
+                .iter()
+                .flat_map(|diagnostic| {
+                    diagnostic_actions(
+                        &uri,
+                        document.as_deref(),
+                        encoding,
+                        &diagnostics_config,
+                        has_workspace,
+                        diagnostic,
                     )
                 })
-                .flatten()
+                .chain(fix_all)
                 .collect(),
         ))
     }
 
     async fn execute_command(
         &self,
-        mut params: lsp_types::ExecuteCommandParams,
+        params: lsp_types::ExecuteCommandParams,
     ) -> Result<Option<Value>> {
-        match WorkspaceCommand::from_str(&params.command) {
-            Ok(WorkspaceCommand::AddToDictionary) => {
-                let word: String = serde_json::from_value(
-                    params
-                        .arguments
-                        .pop()
-                        .invalid_params("AddToDictionary requires argument")?,
-                )
-                .invalid_params("AddToDictionary expects string argument")?;
-                self.state
-                    .send_if_modified(|state| state.dictionary.insert(word));
-                self.diagnose.send_modify(|_| {});
+        let command = WorkspaceCommand::from_str(&params.command)
+            .ok()
+            .invalid_params(format!("unkown workspace command: `{}`", params.command))?;
+        let arguments = Arguments::new(&command, params.arguments);
+        match command {
+            WorkspaceCommand::AddToDictionary => self.add_to_dictionary(arguments).await,
+            WorkspaceCommand::RemoveFromDictionary => self.remove_from_dictionary(arguments).await,
+            WorkspaceCommand::AddToWorkspaceDictionary => {
+                self.add_to_workspace_dictionary(arguments).await
             }
-            Ok(WorkspaceCommand::DisableRule) => {
-                let rule: String = serde_json::from_value(
-                    params
-                        .arguments
-                        .pop()
-                        .invalid_params("DisableRule requires argument")?,
-                )
-                .invalid_params("DisableRule expects string argument")?;
-                self.state
-                    .send_if_modified(|state| state.disabled_rules.insert(rule));
-                self.diagnose.send_modify(|_| {});
+            WorkspaceCommand::DisableRule => self.disable_rule(arguments).await,
+            WorkspaceCommand::DisableRuleInDocument => {
+                self.disable_rule_in_document(arguments).await
             }
-            Err(_) => {
-                return Err(invalid_params!(
-                    "unkown workspace command: `{}`",
-                    params.command
-                ));
+            WorkspaceCommand::EnableRule => self.enable_rule(arguments).await,
+            WorkspaceCommand::ListDisabledRules => Ok(Some(self.list_disabled_rules())),
+            WorkspaceCommand::DisableCategory => self.disable_category(arguments).await,
+            WorkspaceCommand::CheckRange => self.check_range(arguments).await,
+            WorkspaceCommand::ResetState => self.reset_state(arguments).await,
+            WorkspaceCommand::SetLanguage => self.set_language(arguments).await,
+            WorkspaceCommand::DismissFalsePositive => self.dismiss_false_positive(arguments).await,
+            WorkspaceCommand::FixAll => self.fix_all(arguments).await,
+        }
+    }
+
+    async fn unknown_notification(&self, method: String, params: Value) {
+        match method.as_str() {
+            Focus::METHOD => match serde_json::from_value::<FocusParams>(params) {
+                Ok(FocusParams { uri }) => self.diagnose.send_modify(|queue| {
+                    queue.focused = Some(uri);
+                }),
+                Err(e) => error!("{e}"),
+            },
+            _ => error!("unkown notification method: `{method}`"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, PublishDiagnostics,
+    };
+    use lsp_types::request::{DocumentDiagnosticRequest, Request, ShowMessageRequest};
+    use lsp_types::{InitializeParams, Position, Range, TextEdit};
+    use serde_json::json;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("doc-spelling-lsp-{name}-{}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Initialization for a server that is never reached and state in `dir`.
+    fn initialize_params(dir: &Path) -> InitializeParams {
+        let state = dir.join("state.json");
+        state::write(&state, &State::default()).unwrap();
+        InitializeParams {
+            initialization_options: Some(json!({
+                "server": { "type": "Online", "base_url": "http://127.0.0.1", "port": 1 },
+                "state": { "location": state },
+                "diagnostics": { "persistent_cache": false },
+            })),
+            ..Default::default()
+        }
+    }
+
+    fn diagnostic(line: u32, start: u32, end: u32, data: Value) -> lsp_types::Diagnostic {
+        lsp_types::Diagnostic {
+            range: Range::new(Position::new(line, start), Position::new(line, end)),
+            data: Some(data),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fix_all_edits_only_fixes_misspellings_in_prose() {
+        let prose = json!({ "missspelled": "wrld", "replacements": ["world"] });
+        let identifier = json!({
+            "missspelled": "wrld",
+            "replacements": ["world_map"],
+            "identifier": "wrld_map",
+        });
+        let grammar = json!({ "replacements": ["The"] });
+        let edits = fix_all_edits([
+            diagnostic(0, 0, 4, prose),
+            diagnostic(1, 0, 8, identifier),
+            diagnostic(2, 0, 3, grammar),
+        ]);
+        assert_eq!(edits, [TextEdit {
+            range: Range::new(Position::new(0, 0), Position::new(0, 4)),
+            new_text: "world".to_owned(),
+        }]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn exits_successfully_after_shutdown() {
+        let params = initialize_params(&temp_dir("lifecycle"));
+        let exit_code = lsp::testing::run::<Lsp>(move |mut editor| {
+            editor.initialize(params);
+            editor.shutdown();
+        })
+        .await;
+        assert_eq!(exit_code, ExitCode::SUCCESS);
+    }
+
+    #[test]
+    fn falls_back_without_home_directory() {
+        let (client, mut editor) = lsp::testing::TestClient::client();
+        let data_dir = Path::new("/data");
+        let configured = PathBuf::from("/configured");
+        assert_eq!(
+            server_location(Some(configured.clone()), Some(data_dir), Some(&client)),
+            configured
+        );
+        assert_eq!(
+            server_location(None, Some(data_dir), Some(&client)),
+            data_dir.join("language")
+        );
+        let location = env::temp_dir().join("doc-spelling-lsp");
+        assert_eq!(server_location(None, None, Some(&client)), location);
+        let message = editor.notification::<lsp_types::notification::LogMessage>();
+        assert_eq!(message.typ, MessageType::WARNING);
+        assert_eq!(
+            message.message,
+            format!(
+                "unable to find home directory, extracting LanguageTool to `{}`; configure \
+                 `server.location` to fix this",
+                location.display()
+            )
+        );
+
+        // the state is only kept in memory
+        let (_sender, changes) = watch::channel(State::default());
+        let config = config::Config::default();
+        let (state, writer) = initial_state(&config, None, None, changes, &client).unwrap();
+        assert!(state.dictionary.is_empty());
+        assert!(writer.is_none());
+        let message = editor.notification::<lsp_types::notification::ShowMessage>();
+        assert_eq!(message.typ, MessageType::WARNING);
+        assert_eq!(
+            message.message,
+            "unable to find home directory, dictionary and disabled rules will not be persisted; \
+             configure `state.location` to fix this"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn shutdown_persists_latest_state() {
+        let dir = temp_dir("persist");
+        let params = initialize_params(&dir);
+        let exit_code = lsp::testing::run::<Lsp>(move |mut editor| {
+            editor.initialize(params);
+            let response = editor.request::<lsp_types::request::ExecuteCommand>(
+                lsp_types::ExecuteCommandParams {
+                    command: WorkspaceCommand::AddToDictionary.to_string(),
+                    arguments: vec![json!("wrld"), json!("en-US")],
+                    ..Default::default()
+                },
+            );
+            assert!(response.error.is_none(), "{:?}", response.error);
+            editor.shutdown();
+        })
+        .await;
+        assert_eq!(exit_code, ExitCode::SUCCESS);
+        let state = state::read(&dir.join("state.json")).unwrap();
+        assert!(state.dictionary["en-US"].contains("wrld"));
+    }
+
+    #[test]
+    fn invalid_settings_keep_previous_config() {
+        let (client, mut editor) = lsp::testing::TestClient::client();
+        let config = watch::channel(config::Config::default()).0;
+        let state = watch::channel(State::default()).0;
+        let settings = std::sync::Mutex::default();
+        let valid = json!({ "diagnostics": { "delay_ms": 0 } });
+        assert!(apply_settings(
+            valid.clone(),
+            &settings,
+            None,
+            &config,
+            &state,
+            &client
+        ));
+        assert!(!apply_settings(
+            json!({ "diagnostics": { "delay_ms": 10, "cache_size": 0 } }),
+            &settings,
+            None,
+            &config,
+            &state,
+            &client
+        ));
+        assert_eq!(config.borrow().diagnostics.delay_ms, 0);
+        assert_eq!(*settings.lock().unwrap(), Some(valid));
+        let message = editor.notification::<lsp_types::notification::ShowMessage>();
+        assert_eq!(message.typ, MessageType::ERROR);
+        assert_eq!(
+            message.message,
+            "unable to apply configuration: `diagnostics.cache_size` must be at least 1"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn empty_settings_keep_initialization_options() {
+        let params = initialize_params(&temp_dir("empty-settings"));
+        let exit_code = lsp::testing::run::<Lsp>(move |mut editor| {
+            editor.initialize(params);
+            for settings in [Value::Null, json!({})] {
+                editor.notify::<lsp_types::notification::DidChangeConfiguration>(
+                    lsp_types::DidChangeConfigurationParams { settings },
+                );
             }
+            editor.shutdown();
+            // the server and state location differ from the defaults
+            let restart_warning = editor.received.iter().find(|message| {
+                matches!(message, lsp_server::Message::Notification(notification)
+                    if notification.params.to_string().contains("require a restart"))
+            });
+            assert!(restart_warning.is_none(), "{restart_warning:?}");
+        })
+        .await;
+        assert_eq!(exit_code, ExitCode::SUCCESS);
+    }
+
+    #[test]
+    fn arguments_are_taken_in_order() {
+        let mut arguments = Arguments::new(&WorkspaceCommand::SetLanguage, vec![
+            json!("de-DE"),
+            Value::Null,
+        ]);
+        assert_eq!(arguments.required::<String>("language").unwrap(), "de-DE");
+        assert_eq!(arguments.optional::<Url>("document url").unwrap(), None);
+        assert_eq!(arguments.optional::<Url>("document url").unwrap(), None);
+        let error = arguments.required::<String>("rule").unwrap_err();
+        assert_eq!(error.message, "SetLanguage requires rule argument");
+        let message = Arguments::new(&WorkspaceCommand::DisableRule, vec![json!(1)])
+            .required::<String>("rule")
+            .unwrap_err()
+            .message;
+        assert!(message.starts_with("DisableRule expects rule argument"));
+    }
+
+    #[test]
+    fn fix_all_edits_sorts_and_skips_overlapping_edits() {
+        let fix = |replacement| json!({ "missspelled": "x", "replacements": [replacement] });
+        let edits = fix_all_edits([
+            diagnostic(1, 0, 4, fix("second")),
+            diagnostic(0, 2, 6, fix("overlapping")),
+            diagnostic(0, 0, 4, fix("first")),
+            diagnostic(0, 4, 8, fix("adjacent")),
+        ]);
+        let new_texts: Vec<_> = edits.iter().map(|edit| edit.new_text.as_str()).collect();
+        assert_eq!(new_texts, ["first", "adjacent", "second"]);
+    }
+
+    /// Checker reporting every occurrence of `word` as misspelling, to be
+    /// replaced by `replacement`.
+    fn misspelling_checker(word: &'static str, replacement: &'static str) -> Checker {
+        Checker::mock(move |text| {
+            text.match_indices(word)
+                .map(|(offset, _)| {
+                    serde_json::from_value(json!({
+                        "message": "Possible spelling mistake found.",
+                        "shortMessage": "Spelling mistake",
+                        "replacements": [{ "value": replacement }],
+                        // LanguageTool counts UTF-16 code units
+                        "offset": text[..offset].encode_utf16().count(),
+                        "length": word.encode_utf16().count(),
+                        "context": { "text": "", "offset": 0, "length": 0 },
+                        "sentence": "",
+                        "type": { "typeName": "Other" },
+                        "rule": {
+                            "id": "MORFOLOGIK_RULE_EN_US",
+                            "description": "Possible spelling mistake",
+                            "issueType": "misspelling",
+                            "category": { "id": "TYPOS", "name": "Possible Typo" },
+                        },
+                        "ignoreForIncompleteSentence": false,
+                        "contextForSureMatch": 0,
+                    }))
+                    .expect("match is valid")
+                })
+                .collect()
+        })
+    }
+
+    /// Applies the document edits of the code `action` to `document`.
+    fn apply_action(
+        document: &str,
+        encoding: &lsp_types::PositionEncodingKind,
+        action: &CodeActionOrCommand,
+    ) -> String {
+        let CodeActionOrCommand::CodeAction(CodeAction {
+            edit:
+                Some(lsp_types::WorkspaceEdit {
+                    document_changes: Some(DocumentChanges::Edits(changes)),
+                    ..
+                }),
+            ..
+        }) = action
+        else {
+            panic!("expected a document edit, got {action:?}");
         };
-        Ok(None)
+        let mut edits: Vec<_> = changes
+            .iter()
+            .flat_map(|change| &change.edits)
+            .map(|edit| match edit {
+                OneOf::Left(edit) => edit,
+                OneOf::Right(edit) => &edit.text_edit,
+            })
+            .collect();
+        // last first, so the positions of the remaining edits stay valid
+        edits.sort_by_key(|edit| std::cmp::Reverse(edit.range.start));
+        let mut document = document.to_owned();
+        for edit in edits {
+            diagnostic::apply_change(&mut document, encoding, edit.range, &edit.new_text);
+        }
+        document
+    }
+
+    #[tokio::test]
+    async fn code_actions_fix_diagnosed_misspellings() {
+        let uri = Url::parse("file:///lib.rs").unwrap();
+        let document = "//! Größe 🦀: the colr of the colr.\nfn main() {}\n";
+        let config = config::Diagnostics::default();
+        let checker = misspelling_checker("colr", "color");
+        for encoding in [
+            lsp_types::PositionEncodingKind::UTF8,
+            lsp_types::PositionEncodingKind::UTF16,
+            lsp_types::PositionEncodingKind::UTF32,
+        ] {
+            let diagnostics = diagnose(
+                document,
+                "rust",
+                &encoding,
+                &checker,
+                &State::default(),
+                &config,
+            )
+            .await
+            .unwrap();
+            assert_eq!(diagnostics.len(), 2, "in {encoding:?}");
+            let actions = diagnostic_actions(
+                &uri,
+                Some(document),
+                &encoding,
+                &config,
+                false,
+                &diagnostics[1],
+            );
+            let titles: Vec<_> = actions
+                .iter()
+                .map(|action| match action {
+                    CodeActionOrCommand::CodeAction(action) => &action.title,
+                    CodeActionOrCommand::Command(command) => &command.title,
+                })
+                .collect();
+            assert_eq!(titles[..2], [
+                "replace with `color`",
+                "replace all `colr` with `color`"
+            ]);
+            assert_eq!(
+                apply_action(document, &encoding, &actions[0]),
+                "//! Größe 🦀: the colr of the color.\nfn main() {}\n",
+                "in {encoding:?}"
+            );
+            assert_eq!(
+                apply_action(document, &encoding, &actions[1]),
+                "//! Größe 🦀: the color of the color.\nfn main() {}\n",
+                "in {encoding:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn code_actions_only_rename_whole_identifiers() {
+        let uri = Url::parse("file:///lib.rs").unwrap();
+        let document = "fn colr_map() {}\nfn main() { colr_map(); }\n";
+        let encoding = lsp_types::PositionEncodingKind::UTF8;
+        let config = config::Diagnostics {
+            identifiers: true,
+            ..Default::default()
+        };
+        let checker = misspelling_checker("colr", "color");
+        let diagnostics = diagnose(
+            document,
+            "rust",
+            &encoding,
+            &checker,
+            &State::default(),
+            &config,
+        )
+        .await
+        .unwrap();
+        // only the definition is reported
+        assert_eq!(diagnostics.len(), 1);
+        let actions = diagnostic_actions(
+            &uri,
+            Some(document),
+            &encoding,
+            &config,
+            false,
+            &diagnostics[0],
+        );
+        let CodeActionOrCommand::CodeAction(rename) = &actions[0] else {
+            panic!("expected a rename, got {actions:?}");
+        };
+        assert_eq!(rename.title, "rename all `colr_map` to `color_map`");
+        assert!(
+            actions[1..]
+                .iter()
+                .all(|action| matches!(action, CodeActionOrCommand::Command(_)))
+        );
+        assert_eq!(
+            apply_action(document, &encoding, &actions[0]),
+            "fn color_map() {}\nfn main() { color_map(); }\n"
+        );
+    }
+
+    #[test]
+    fn queue_pops_focused_documents_first_and_delayed_ones_when_due() {
+        let [first, focused, delayed] =
+            ["a", "b", "c"].map(|name| Url::parse(&format!("file:///{name}.rs")).unwrap());
+        let due = Instant::now() + Duration::from_secs(60);
+        let mut queue = Queue {
+            documents: [first.clone(), focused.clone()].into(),
+            delayed: [(delayed.clone(), due)].into(),
+            focused: Some(focused.clone()),
+        };
+        assert_eq!(queue.pop(), Some(focused));
+        assert_eq!(queue.pop(), Some(first));
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.next_due(), Some(due));
+        queue.delayed.insert(delayed.clone(), Instant::now());
+        assert_eq!(queue.pop(), Some(delayed));
+        assert_eq!(queue.next_due(), None);
+    }
+
+    #[test]
+    fn noisy_rules_skip_misspellings_and_suggested_rules() {
+        let rule = json!({ "rule": "COMMA", "replacements": [] });
+        let misspelling = json!({ "missspelled": "wrld", "rule": "SPELLING", "replacements": [] });
+        let diagnostics: Vec<_> = (0..NOISY_RULE_DIAGNOSTICS)
+            .flat_map(|line| {
+                let line = u32::try_from(line).unwrap();
+                [
+                    diagnostic(line, 0, 1, rule.clone()),
+                    diagnostic(line, 2, 3, misspelling.clone()),
+                ]
+            })
+            .collect();
+        let mut suggested = HashSet::new();
+        assert!(noisy_rules(&diagnostics[1..], &mut suggested).is_empty());
+        assert_eq!(noisy_rules(&diagnostics, &mut suggested), [(
+            "COMMA".to_owned(),
+            NOISY_RULE_DIAGNOSTICS
+        )]);
+        assert!(noisy_rules(&diagnostics, &mut suggested).is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn suggestion_disables_chosen_rule() {
+        let (client, mut editor) = lsp::testing::TestClient::client();
+        let rule = json!({ "rule": "COMMA", "replacements": [] });
+        let diagnostics = vec![diagnostic(0, 0, 1, rule); NOISY_RULE_DIAGNOSTICS];
+        let state = Arc::new(watch::channel(State::default()).0);
+        let (sender, disabled) = std::sync::mpsc::channel();
+        suggest_disabling_noisy_rules(
+            &client,
+            "lib.rs",
+            &diagnostics,
+            &std::sync::Mutex::default(),
+            Arc::downgrade(&state),
+            move || sender.send(()).unwrap(),
+        );
+        let editor = std::thread::spawn(move || {
+            editor.results.insert(
+                ShowMessageRequest::METHOD,
+                json!({ "title": "Disable `COMMA`" }),
+            );
+            editor.server_request::<ShowMessageRequest>()
+        });
+        let params = editor.join().unwrap();
+        assert_eq!(
+            params.message,
+            format!("`COMMA` reported {NOISY_RULE_DIAGNOSTICS} problems in `lib.rs`.")
+        );
+        disabled.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(state.borrow().disabled_rules.contains("COMMA"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn did_close_clears_diagnostics_of_open_documents() {
+        let mut params = initialize_params(&temp_dir("close"));
+        // pulled diagnostics are not checked in the background
+        params.capabilities.text_document = Some(lsp_types::TextDocumentClientCapabilities {
+            diagnostic: Some(lsp_types::DiagnosticClientCapabilities::default()),
+            ..Default::default()
+        });
+        let exit_code = lsp::testing::run::<Lsp>(move |mut editor| {
+            editor.initialize(params);
+            let opened = Url::parse("file:///lib.rs").unwrap();
+            editor.notify::<DidOpenTextDocument>(lsp_types::DidOpenTextDocumentParams {
+                text_document: lsp_types::TextDocumentItem::new(
+                    opened.clone(),
+                    "rust".to_owned(),
+                    0,
+                    "/// Hello wrld\nfn f() {}\n".to_owned(),
+                ),
+            });
+            editor.notify::<DidCloseTextDocument>(lsp_types::DidCloseTextDocumentParams {
+                text_document: lsp_types::TextDocumentIdentifier::new(opened.clone()),
+            });
+            let cleared = editor.notification::<PublishDiagnostics>();
+            assert_eq!(cleared.uri, opened);
+            assert!(cleared.diagnostics.is_empty());
+            // a late change must not open the document again
+            editor.notify::<DidChangeTextDocument>(lsp_types::DidChangeTextDocumentParams {
+                text_document: lsp_types::VersionedTextDocumentIdentifier::new(opened.clone(), 1),
+                content_changes: vec![lsp_types::TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: "/// Hello world\n".to_owned(),
+                }],
+            });
+            let response =
+                editor.request::<DocumentDiagnosticRequest>(lsp_types::DocumentDiagnosticParams {
+                    text_document: lsp_types::TextDocumentIdentifier::new(opened.clone()),
+                    identifier: None,
+                    previous_result_id: None,
+                    work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+                    partial_result_params: lsp_types::PartialResultParams::default(),
+                });
+            assert_eq!(
+                response.error.map(|error| error.code),
+                Some(lsp::ErrorCode::InvalidParams as i32)
+            );
+            editor.notify::<DidCloseTextDocument>(lsp_types::DidCloseTextDocumentParams {
+                text_document: lsp_types::TextDocumentIdentifier::new(
+                    Url::parse("file:///never_opened.rs").unwrap(),
+                ),
+            });
+            editor.shutdown();
+            assert!(!editor.received.iter().any(|message| matches!(
+                message,
+                lsp_server::Message::Notification(notification)
+                    if notification.method == PublishDiagnostics::METHOD
+            )));
+        })
+        .await;
+        assert_eq!(exit_code, ExitCode::SUCCESS);
     }
 }
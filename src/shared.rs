@@ -0,0 +1,265 @@
+//! Sharing one spawned LanguageTool server between all running instances.
+//!
+//! The first instance spawns the server and records it in `server.json` in
+//! the runtime directory, later instances with the same server command connect
+//! to it. Every instance is counted as user, the last one to shut down kills
+//! the server.
+
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, SystemTime};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// Time a server may take to start listening, before it is considered dead.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time after which a lock is considered abandoned, e.g., by a crash.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+const LOCK_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Serialize, Deserialize)]
+struct Registry {
+    /// Program and arguments the server was spawned with, excluding the port.
+    command: Vec<String>,
+    port: u16,
+    /// Process id of the server.
+    pid: u32,
+    /// When the server was last (re)started, it may not listen yet until
+    /// [`STARTUP_TIMEOUT`] after.
+    started: SystemTime,
+    /// Number of instances using the server.
+    users: u32,
+}
+
+/// Program and arguments of `command`, used to only share servers started
+/// the same way.
+pub fn command_line(command: &Command) -> Vec<String> {
+    [command.get_program()]
+        .into_iter()
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+fn dir() -> PathBuf {
+    directories::BaseDirs::new()
+        .and_then(|base_dirs| base_dirs.runtime_dir().map(ToOwned::to_owned))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("doc-spelling-lsp")
+}
+
+/// Exclusive access to the registry of the shared server, released on drop.
+pub struct Lock {
+    dir: PathBuf,
+}
+
+impl Lock {
+    /// Waits until no other instance accesses the registry.
+    pub fn acquire() -> io::Result<Self> {
+        Self::acquire_in(dir())
+    }
+
+    /// Waits until no other instance accesses the registry in `dir`.
+    fn acquire_in(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let lock = dir.join("server.lock");
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&lock) {
+                Ok(_) => return Ok(Self { dir }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let abandoned = fs::metadata(&lock)
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok())
+                        .is_some_and(|elapsed| elapsed > LOCK_TIMEOUT);
+                    if abandoned {
+                        warn!("removing abandoned lock `{}`", lock.display());
+                        _ = fs::remove_file(&lock);
+                    } else {
+                        sleep(LOCK_INTERVAL);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn location(&self) -> PathBuf {
+        self.dir.join("server.json")
+    }
+
+    fn read(&self) -> Option<Registry> {
+        serde_json::from_slice(&fs::read(self.location()).ok()?).ok()
+    }
+
+    fn write(&self, registry: &Registry) -> io::Result<()> {
+        fs::write(
+            self.location(),
+            serde_json::to_string(registry).expect("registry should be serializable"),
+        )
+    }
+
+    /// Port of the shared server started with `command`, if it is running
+    /// or still starting, counting this instance as user.
+    pub fn connect(&self, command: &[String]) -> Option<u16> {
+        let mut registry = self.read()?;
+        // the port could be reused by another program after a crash
+        if registry.command != command || !is_running(registry.pid) {
+            return None;
+        }
+        let starting = registry
+            .started
+            .elapsed()
+            .is_ok_and(|elapsed| elapsed < STARTUP_TIMEOUT);
+        if !starting && TcpStream::connect(("localhost", registry.port)).is_err() {
+            return None;
+        }
+        registry.users += 1;
+        self.write(&registry).ok()?;
+        Some(registry.port)
+    }
+
+    /// Records the server spawned by this instance, replacing any previous
+    /// one.
+    pub fn register(&self, command: Vec<String>, port: u16, pid: u32) -> io::Result<()> {
+        self.write(&Registry {
+            command,
+            port,
+            pid,
+            started: SystemTime::now(),
+            users: 1,
+        })
+    }
+
+    /// Updates the process id after the server was restarted.
+    pub fn restarted(&self, pid: u32) -> io::Result<()> {
+        let Some(mut registry) = self.read() else {
+            return Ok(());
+        };
+        registry.pid = pid;
+        registry.started = SystemTime::now();
+        self.write(&registry)
+    }
+
+    /// Stops counting this instance as user, returns the process id of the
+    /// server if this was the last one.
+    pub fn release(&self) -> io::Result<Option<u32>> {
+        let Some(mut registry) = self.read() else {
+            return Ok(None);
+        };
+        registry.users = registry.users.saturating_sub(1);
+        if registry.users == 0 {
+            fs::remove_file(self.location())?;
+            Ok(Some(registry.pid))
+        } else {
+            self.write(&registry).map(|()| None)
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        _ = fs::remove_file(self.dir.join("server.lock"));
+    }
+}
+
+/// Whether the process `pid` exists.
+fn is_running(pid: u32) -> bool {
+    let pid = pid.to_string();
+    if cfg!(windows) {
+        Command::new("tasklist")
+            .arg("/FI")
+            .arg(format!("PID eq {pid}"))
+            .arg("/NH")
+            .output()
+            .is_ok_and(|output| {
+                String::from_utf8_lossy(&output.stdout)
+                    .split_whitespace()
+                    .any(|word| word == pid)
+            })
+    } else {
+        Command::new("kill")
+            .arg("-0")
+            .arg(pid)
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+}
+
+/// Kills a server spawned by another instance.
+pub fn kill(pid: u32) -> io::Result<()> {
+    let status = if cfg!(windows) {
+        Command::new("taskkill")
+            .arg("/PID")
+            .arg(pid.to_string())
+            .arg("/F")
+            .status()?
+    } else {
+        Command::new("kill").arg(pid.to_string()).status()?
+    };
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "killing {pid} failed with {status}"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn connects_to_live_servers_by_their_start_time() {
+        let dir = env::temp_dir().join(format!("doc-spelling-lsp-shared-{}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        let lock = Lock::acquire_in(dir.clone()).unwrap();
+        let command = vec!["languagetool".to_owned()];
+        // the test itself stands in for the server process
+        let pid = std::process::id();
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        lock.register(command.clone(), port, pid).unwrap();
+        assert_eq!(lock.connect(&["other".to_owned()]), None);
+        drop(listener);
+        // still starting, even though nothing listens yet
+        assert_eq!(lock.connect(&command), Some(port));
+        assert_eq!(lock.read().unwrap().users, 2);
+
+        // rewriting the registry does not extend the startup window
+        lock.write(&Registry {
+            started: SystemTime::now() - 2 * STARTUP_TIMEOUT,
+            ..lock.read().unwrap()
+        })
+        .unwrap();
+        assert_eq!(lock.connect(&command), None);
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        assert_eq!(lock.connect(&command), Some(port));
+
+        let mut exited = Command::new(env::current_exe().unwrap())
+            .arg("--list")
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        exited.wait().unwrap();
+        lock.restarted(exited.id()).unwrap();
+        assert_eq!(lock.connect(&command), None);
+        drop(listener);
+
+        assert_eq!(lock.release().unwrap(), None);
+        assert_eq!(lock.read().unwrap().users, 2);
+        drop(lock);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,69 +1,587 @@
-use std::collections::{BTreeSet, HashSet};
-use std::fs;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Weak;
+use std::time::Duration;
 
-use log::error;
-use serde::{Deserialize, Serialize};
+use log::{error, warn};
+use lsp_types::Url;
+use serde::{Deserialize, Deserializer, Serialize};
+use smart_default::SmartDefault;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
 
 use crate::lsp::Context;
 use crate::{config, Result};
 
-#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+/// Language used for text not preceded by a `language` directive.
+pub const DEFAULT_LANGUAGE: &str = "en-US";
+
+/// Lets LanguageTool detect the language of each text.
+pub const AUTO_LANGUAGE: &str = "auto";
+
+fn default_language() -> String {
+    DEFAULT_LANGUAGE.to_owned()
+}
+
+#[derive(Serialize, Deserialize, SmartDefault, Debug, Clone)]
 #[must_use]
 pub struct State {
     pub disabled_rules: BTreeSet<String>,
-    pub dictionary: HashSet<String>,
+    /// Words by language.
+    #[serde(default, deserialize_with = "deserialize_dictionary")]
+    pub dictionary: HashMap<String, HashSet<String>>,
+    #[serde(default)]
+    pub disabled_categories: BTreeSet<String>,
+    #[serde(default)]
+    pub false_positives: BTreeSet<FalsePositive>,
+    /// Language documents are checked in.
+    #[default(_code = "default_language()")]
+    #[serde(default = "default_language")]
+    pub language: String,
+    /// Languages of single documents, overriding [`State::language`].
+    #[serde(default)]
+    pub document_languages: BTreeMap<Url, String>,
+    /// Rules disabled in single documents, in addition to
+    /// [`State::disabled_rules`].
+    #[serde(default)]
+    pub document_disabled_rules: BTreeMap<Url, BTreeSet<String>>,
+    /// Words loaded from [`config::State::dictionary_files`].
+    #[serde(skip)]
+    pub external_dictionary: HashSet<String>,
+    /// Words loaded from the [`workspace_dictionary`].
+    #[serde(skip)]
+    pub workspace_dictionary: HashSet<String>,
 }
 
-pub fn update(
-    mut state: tokio::sync::watch::Receiver<State>,
+/// A single dismissed match, identified by the rule, the matched text and the
+/// sentence it occurred in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FalsePositive {
+    pub rule: String,
+    pub text: String,
+    pub sentence: String,
+}
+
+impl State {
+    pub fn in_dictionary(&self, language: &str, word: &str) -> bool {
+        self.dictionary
+            .get(language)
+            .is_some_and(|dictionary| dictionary.contains(word))
+            || self.external_dictionary.contains(word)
+            || self.workspace_dictionary.contains(word)
+    }
+
+    /// Replaces everything persisted by `persisted`, keeping the words loaded
+    /// from dictionary files.
+    ///
+    /// New fields that are not persisted have to be kept here as well.
+    pub fn replace_persisted(&mut self, mut persisted: State) {
+        persisted.external_dictionary = mem::take(&mut self.external_dictionary);
+        persisted.workspace_dictionary = mem::take(&mut self.workspace_dictionary);
+        *self = persisted;
+    }
+
+    /// Applies the settings of `document`, i.e., uses the language set for it,
+    /// or else `default`, if any, as [`State::language`] and adds its disabled
+    /// rules.
+    pub fn use_document(&mut self, document: &Url, default: Option<&str>) {
+        if let Some(rules) = self.document_disabled_rules.get(document) {
+            self.disabled_rules.extend(rules.iter().cloned());
+        }
+        if let Some(language) = self
+            .document_languages
+            .get(document)
+            .map(String::as_str)
+            .or(default)
+        {
+            self.language = language.to_owned();
+        }
+    }
+
+    /// State to check `document` with, i.e., with its settings applied by
+    /// [`State::use_document`], defaulting to the language configured for
+    /// it.
+    pub fn for_document(
+        &self,
+        config: &config::Config,
+        document: &Url,
+        language_id: &str,
+    ) -> State {
+        let mut state = self.clone();
+        state.use_document(document, config.language(document, language_id));
+        state
+    }
+}
+
+/// Also accepts the flat list of words used before dictionaries were split by
+/// language, these are assigned to [`DEFAULT_LANGUAGE`].
+fn deserialize_dictionary<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<HashMap<String, HashSet<String>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Dictionary {
+        ByLanguage(HashMap<String, HashSet<String>>),
+        Flat(HashSet<String>),
+    }
+    Ok(match Dictionary::deserialize(deserializer)? {
+        Dictionary::ByLanguage(dictionary) => dictionary,
+        Dictionary::Flat(words) => [(default_language(), words)].into(),
+    })
+}
+
+/// Resolves the location of the state file, creating the default one if
+/// necessary.
+///
+/// Returns `None` if there is no location configured and no home directory to
+/// use as default.
+pub fn location(state_config: &config::State, workspace: Option<&Path>) -> Result<Option<PathBuf>> {
+    let base_dirs = directories::BaseDirs::new();
+    location_in(
+        state_config,
+        workspace,
+        base_dirs.as_ref().map(directories::BaseDirs::config_dir),
+    )
+}
+
+/// [`location`] with the configuration directory of the user, `None` if there
+/// is no home directory.
+fn location_in(
     state_config: &config::State,
-) -> Result<State> {
-    let state_location = if let Some(location) = state_config.location.clone() {
-        if location.is_dir() {
+    workspace: Option<&Path>,
+    config_dir: Option<&Path>,
+) -> Result<Option<PathBuf>> {
+    if let Some(location) = state_config.location.clone() {
+        return Ok(Some(if location.is_dir() {
             location.join("state.json")
         } else {
             location
+        }));
+    }
+    if state_config.scope == config::Scope::Workspace {
+        if let Some(workspace) = workspace {
+            return workspace_location(workspace).map(Some);
+        }
+        warn!("no workspace root, using global state");
+    }
+    let Some(config_dir) = config_dir else {
+        warn!("unable to find home directory, keeping state in memory");
+        return Ok(None);
+    };
+    default_location(&config_dir.join("doc-spelling-lsp")).map(Some)
+}
+
+/// `state.json` in the `.doc-spelling` directory of `workspace`, next to the
+/// project config and dictionary, moved there from `.doc-spelling-lsp` it
+/// was kept in before.
+fn workspace_location(workspace: &Path) -> Result<PathBuf> {
+    let dir = workspace.join(".doc-spelling");
+    let location = dir.join("state.json");
+    let legacy = workspace.join(".doc-spelling-lsp").join("state.json");
+    if legacy.exists() && !location.exists() {
+        if let Err(e) = fs::create_dir_all(&dir).and_then(|()| fs::rename(&legacy, &location)) {
+            warn!("unable to move state `{}`: {e}", legacy.display());
+            return Ok(legacy);
+        }
+        // only remove the directory if it is empty
+        _ = fs::remove_dir(workspace.join(".doc-spelling-lsp"));
+    }
+    default_location(&dir)
+}
+
+/// `state.json` in `dir`, created with the default state if it does not
+/// exist.
+fn default_location(dir: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(dir).internal_error("unable to create state location")?;
+    let state_location = dir.join("state.json");
+    if !state_location.exists() {
+        write(&state_location, &State::default())?;
+    }
+    Ok(state_location)
+}
+
+pub fn write(location: &Path, state: &State) -> Result<()> {
+    fs::write(
+        location,
+        serde_json::to_string(state).expect("state should be serializable"),
+    )
+    .internal_error(format!("unable to write state at `{}`", location.display()))
+}
+
+/// Reads the state at `state_location` and writes every change of `state`
+/// there, until all senders are dropped.
+///
+/// The returned writer finishes once the latest change is written, so it has
+/// to be awaited before exiting.
+pub fn update(
+    mut state: tokio::sync::watch::Receiver<State>,
+    state_location: &Path,
+) -> Result<(State, JoinHandle<()>)> {
+    let initial = read(state_location)?;
+    let state_location = state_location.to_owned();
+    // only one writer, so older changes never overwrite newer ones
+    let writer = tokio::spawn(async move {
+        // unseen changes are still reported after the senders are dropped
+        while state.changed().await.is_ok() {
+            let current = state.borrow_and_update().clone();
+            if let Err(e) = write(&state_location, &current) {
+                error!("{e}");
+            };
         }
+    });
+    Ok((initial, writer))
+}
+
+/// Interval to check files for changes by others, e.g., another editor or
+/// manual edits.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Calls `on_change` with `owner` whenever the modification time of `file`
+/// changes, until `owner` is dropped.
+pub fn poll_file<T: Send + Sync + 'static>(
+    file: PathBuf,
+    owner: Weak<T>,
+    on_change: impl Fn(&T, &Path) + Send + 'static,
+) {
+    let modified = |file: &Path| fs::metadata(file).and_then(|m| m.modified()).ok();
+    tokio::spawn(async move {
+        let mut last_modified = modified(&file);
+        loop {
+            sleep(RELOAD_INTERVAL).await;
+            let Some(owner) = owner.upgrade() else {
+                break;
+            };
+            let current_modified = modified(&file);
+            if current_modified != last_modified {
+                last_modified = current_modified;
+                on_change(&owner, &file);
+            }
+        }
+    });
+}
+
+/// Reloads the state when `state_location` is changed by others and calls
+/// `on_reload` afterwards, until `state` is dropped.
+pub fn reload(
+    state: Weak<tokio::sync::watch::Sender<State>>,
+    state_location: PathBuf,
+    on_reload: impl Fn() + Send + 'static,
+) {
+    poll_file(state_location, state, move |state, state_location| {
+        let Ok(content) = fs::read_to_string(state_location) else {
+            return;
+        };
+        let current =
+            serde_json::to_string(&*state.borrow()).expect("state should be serializable");
+        // written by ourselves
+        if content == current {
+            return;
+        }
+        let reloaded: State = match serde_json::from_str(&content) {
+            Ok(reloaded) => reloaded,
+            Err(e) => {
+                warn!("unable to reload state `{}`: {e}", state_location.display());
+                return;
+            }
+        };
+        state.send_modify(|state| state.replace_persisted(reloaded));
+        on_reload();
+    });
+}
+
+pub fn read(state_location: &Path) -> Result<State> {
+    serde_json::from_slice(&fs::read(state_location).internal_error(format!(
+        "unable to read from state location: `{}`",
+        state_location.display()
+    ))?)
+    .internal_error("unable to deserialize state")
+}
+
+/// Dictionary of a workspace, one word per line, meant to be committed with
+/// the project.
+pub fn workspace_dictionary(workspace: &Path) -> PathBuf {
+    workspace.join(".doc-spelling").join("dictionary.txt")
+}
+
+/// Loads the words of `file` if it exists.
+pub fn load_workspace_dictionary(file: &Path) -> HashSet<String> {
+    if file.exists() {
+        load_dictionaries(&[file.to_owned()])
     } else {
-        let state_location = directories::BaseDirs::new()
-            .expect("should be able to find home directory")
-            .config_dir()
-            .join("doc-spelling-lsp");
-        fs::create_dir_all(&state_location).internal_error("unable to create state location")?;
-        let state_location = state_location.join("state.json");
-        if !state_location.exists() {
-            fs::write(
-                &state_location,
-                serde_json::to_string(&State::default()).expect("state can be serialized"),
-            )
-            .internal_error(format!(
-                "unable to write state at `{}`",
-                state_location.display()
-            ))?;
+        HashSet::new()
+    }
+}
+
+/// Appends `word` to the dictionary `file`, creating it if necessary.
+pub fn append_to_dictionary(file: &Path, word: &str) -> Result<()> {
+    let error = || format!("unable to write dictionary `{}`", file.display());
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent).internal_error(error())?;
+    }
+    // don't join the word with an unterminated last line
+    let separator = fs::read(file)
+        .is_ok_and(|content| !content.is_empty() && !content.ends_with(b"\n"))
+        .then_some("\n")
+        .unwrap_or_default();
+    let mut dictionary = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)
+        .internal_error(error())?;
+    writeln!(dictionary, "{separator}{word}").internal_error(error())
+}
+
+/// Loads the words of all dictionary files, skipping unreadable files and
+/// malformed entries.
+pub fn load_dictionaries(files: &[PathBuf]) -> HashSet<String> {
+    let mut words = HashSet::new();
+    for file in files {
+        match fs::read_to_string(file) {
+            Ok(content) => words.extend(parse_dictionary(file, &content)),
+            Err(e) => warn!("unable to read dictionary `{}`: {e}", file.display()),
+        }
+    }
+    words
+}
+
+fn parse_dictionary(file: &Path, content: &str) -> Vec<String> {
+    let hunspell = match file.extension().and_then(OsStr::to_str) {
+        Some("json") => {
+            return serde_json::from_str(content).unwrap_or_else(|e| {
+                warn!(
+                    "dictionary `{}` is not a json array of strings: {e}",
+                    file.display()
+                );
+                Vec::new()
+            });
+        }
+        Some("dic") => true,
+        Some("txt") => false,
+        _ => {
+            warn!(
+                "unknown dictionary format `{}`, reading as one word per line",
+                file.display()
+            );
+            false
         }
-        state_location
     };
+    let mut lines = content.lines().enumerate().peekable();
+    // hunspell dictionaries start with the (approximate) word count
+    if hunspell
+        && lines
+            .peek()
+            .is_some_and(|(_, l)| l.trim().parse::<usize>().is_ok())
     {
-        let state_location = state_location.clone();
-        // update state on disk
-        tokio::spawn(async move {
-            loop {
-                if state.changed().await.is_err() {
-                    break;
-                }
-                if let Err(e) = fs::write(
-                    &state_location,
-                    serde_json::to_string(&state.borrow().clone())
-                        .expect("state should be serializable"),
-                ) {
-                    error!("{e:?}");
-                };
+        lines.next();
+    }
+    lines
+        .filter_map(|(idx, line)| {
+            let word = if hunspell {
+                line.split(['/', '\t']).next().unwrap_or_default()
+            } else {
+                line
+            }
+            .trim();
+            if word.is_empty() {
+                None
+            } else if word.contains(char::is_whitespace) {
+                warn!(
+                    "skipping malformed line {} in dictionary `{}`: `{line}`",
+                    idx + 1,
+                    file.display()
+                );
+                None
+            } else {
+                Some(word.to_owned())
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replace_persisted_keeps_loaded_dictionaries() {
+        let mut state = State::default();
+        state.disabled_rules.insert("RULE".to_owned());
+        state.external_dictionary.insert("external".to_owned());
+        state.workspace_dictionary.insert("workspace".to_owned());
+        state.replace_persisted(State::default());
+        assert!(state.disabled_rules.is_empty());
+        assert!(state.in_dictionary(DEFAULT_LANGUAGE, "external"));
+        assert!(state.in_dictionary(DEFAULT_LANGUAGE, "workspace"));
+    }
+
+    #[test]
+    fn for_document_applies_document_settings() {
+        let markdown = Url::parse("file:///project/README.md").unwrap();
+        let rust = Url::parse("file:///project/src/lib.rs").unwrap();
+        let mut config = config::Config::default();
+        config
+            .languages
+            .insert("*.md".to_owned(), "de-DE".to_owned());
+        config
+            .languages
+            .insert("rust".to_owned(), "en-GB".to_owned());
+        let mut state = State::default();
+        state.disabled_rules.insert("GLOBAL".to_owned());
+        state
+            .document_disabled_rules
+            .insert(rust.clone(), ["LOCAL".to_owned()].into());
+
+        let readme = state.for_document(&config, &markdown, "markdown");
+        assert_eq!(readme.language, "de-DE");
+        assert_eq!(readme.disabled_rules, ["GLOBAL".to_owned()].into());
+        let lib = state.for_document(&config, &rust, "rust");
+        assert_eq!(lib.language, "en-GB");
+        assert_eq!(
+            lib.disabled_rules,
+            ["GLOBAL".to_owned(), "LOCAL".to_owned()].into()
+        );
+
+        state
+            .document_languages
+            .insert(rust.clone(), "fr".to_owned());
+        assert_eq!(state.for_document(&config, &rust, "rust").language, "fr");
+        // the state itself is unchanged
+        assert_eq!(state.language, DEFAULT_LANGUAGE);
+        assert_eq!(state.disabled_rules.len(), 1);
+    }
+
+    #[test]
+    fn write_persists_false_positives() {
+        let location = temp_dir("false-positives").join("state.json");
+        let false_positive = FalsePositive {
+            rule: "RULE".to_owned(),
+            text: "wrld".to_owned(),
+            sentence: "Hello wrld.".to_owned(),
+        };
+        let mut state = State::default();
+        state.false_positives.insert(false_positive.clone());
+        write(&location, &state).unwrap();
+        assert_eq!(
+            read(&location).unwrap().false_positives,
+            [false_positive].into()
+        );
+    }
+
+    /// Empty directory for the test `name`.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("doc-spelling-lsp-{name}-{}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn location_without_home_directory_keeps_state_in_memory() {
+        let config = config::State::default();
+        assert_eq!(location_in(&config, None, None).unwrap(), None);
+        let config_dir = temp_dir("config-dir");
+        let location = location_in(&config, None, Some(&config_dir))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            location,
+            config_dir.join("doc-spelling-lsp").join("state.json")
+        );
+        assert!(location.exists());
+        fs::remove_dir_all(config_dir).unwrap();
+    }
+
+    #[test]
+    fn workspace_location_moves_legacy_state() {
+        let workspace = temp_dir("legacy-state");
+        let legacy = workspace.join(".doc-spelling-lsp");
+        fs::create_dir(&legacy).unwrap();
+        let mut state = State::default();
+        state.disabled_rules.insert("RULE".to_owned());
+        write(&legacy.join("state.json"), &state).unwrap();
+
+        let location = workspace_location(&workspace).unwrap();
+        assert_eq!(location, workspace.join(".doc-spelling").join("state.json"));
+        assert!(read(&location).unwrap().disabled_rules.contains("RULE"));
+        assert!(!legacy.exists());
+        fs::remove_dir_all(workspace).unwrap();
+    }
+
+    #[test]
+    fn parse_dictionary_reads_json_arrays() {
+        let words = parse_dictionary(Path::new("words.json"), r#"["wrld", "Größe"]"#);
+        assert_eq!(words, ["wrld", "Größe"]);
+        assert!(parse_dictionary(Path::new("words.json"), r#"{ "wrld": 1 }"#).is_empty());
+    }
+
+    #[test]
+    fn parse_dictionary_skips_hunspell_count_and_flags() {
+        let words = parse_dictionary(
+            Path::new("words.dic"),
+            "3\nwrld/S\nGröße/MN\ncolr\tpo:noun\n",
+        );
+        assert_eq!(words, ["wrld", "Größe", "colr"]);
+        // a word can only be skipped as count on the first line
+        let words = parse_dictionary(Path::new("words.dic"), "wrld\n42\n");
+        assert_eq!(words, ["wrld", "42"]);
+    }
+
+    #[test]
+    fn parse_dictionary_reads_one_word_per_line() {
+        let words = parse_dictionary(Path::new("words.txt"), "wrld\n\n  colr  \r\n2\n");
+        assert_eq!(words, ["wrld", "colr", "2"]);
+    }
+
+    #[test]
+    fn parse_dictionary_skips_malformed_lines() {
+        let words = parse_dictionary(Path::new("words.txt"), "wrld\ntwo words\ncolr");
+        assert_eq!(words, ["wrld", "colr"]);
+        let words = parse_dictionary(Path::new("words.dic"), "2\nwrld/S\ntwo words/S");
+        assert_eq!(words, ["wrld"]);
+    }
+
+    #[test]
+    fn load_dictionaries_skips_unreadable_files() {
+        let dir = temp_dir("dictionaries");
+        let json = dir.join("words.json");
+        let dic = dir.join("words.dic");
+        fs::write(&json, r#"["wrld"]"#).unwrap();
+        fs::write(&dic, "1\ncolr/S\n").unwrap();
+        let words = load_dictionaries(&[json, dir.join("missing.txt"), dic]);
+        assert_eq!(words, ["wrld".to_owned(), "colr".to_owned()].into());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn poll_file_reports_changes_until_dropped() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let dir = temp_dir("poll-file");
+        let file = dir.join("state.json");
+        fs::write(&file, "{}").unwrap();
+        let owner = Arc::new(());
+        let changes = Arc::new(AtomicUsize::new(0));
+        poll_file(file.clone(), Arc::downgrade(&owner), {
+            let changes = changes.clone();
+            move |(), _| {
+                changes.fetch_add(1, Ordering::Relaxed);
             }
         });
+        sleep(RELOAD_INTERVAL / 2).await;
+        fs::write(&file, "{\"language\": \"de-DE\"}").unwrap();
+        sleep(RELOAD_INTERVAL).await;
+        assert_eq!(changes.load(Ordering::Relaxed), 1);
+
+        drop(owner);
+        fs::write(&file, "{}").unwrap();
+        sleep(RELOAD_INTERVAL).await;
+        assert_eq!(changes.load(Ordering::Relaxed), 1);
+        fs::remove_dir_all(dir).unwrap();
     }
-    serde_json::from_slice(&fs::read(&state_location).internal_error(format!(
-        "unable to read from state location: `{}`",
-        state_location.display()
-    ))?)
-    .internal_error("unable to deserialize state")
 }